@@ -1,45 +1,277 @@
 use std::alloc::{AllocError, Allocator, Layout};
+use std::cell::Cell;
+use std::fmt;
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+/// What memory is used for, allocations are attributed to the category
+/// set with `Alloc::with_category` at the time they are made. Runtime
+/// objects allocate through the VM allocator under the category of their
+/// kind, so they count toward `Alloc::allocated_bytes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AllocCategory {
+    Other,
+    Chunk,
+    String,
+    List,
+    Tuple,
+    Map,
+    Class,
+    Stack,
+}
+
+impl AllocCategory {
+    pub const COUNT: usize = 8;
+    pub const ALL: [AllocCategory; Self::COUNT] = [
+        Self::Other,
+        Self::Chunk,
+        Self::String,
+        Self::List,
+        Self::Tuple,
+        Self::Map,
+        Self::Class,
+        Self::Stack,
+    ];
+
+    pub const fn get_name(&self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Chunk => "chunk",
+            Self::String => "string",
+            Self::List => "list",
+            Self::Tuple => "tuple",
+            Self::Map => "map",
+            Self::Class => "class",
+            Self::Stack => "stack",
+        }
+    }
+}
+
+thread_local! {
+    // Per thread so that threads sharing an `Alloc` do not attribute their
+    // allocations to each other's category
+    static CATEGORY: Cell<AllocCategory> =
+        const { Cell::new(AllocCategory::Other) };
+}
+
+// Restores the previous category on drop, even when unwinding
+struct CategoryGuard {
+    previous: AllocCategory,
+}
+
+impl CategoryGuard {
+    fn new(category: AllocCategory) -> Self {
+        Self {
+            previous: CATEGORY.replace(category),
+        }
+    }
+}
+
+impl Drop for CategoryGuard {
+    fn drop(&mut self) {
+        CATEGORY.set(self.previous);
+    }
+}
+
+#[derive(Debug)]
+struct CategoryCounters {
+    bytes: AtomicUsize,
+    count: AtomicUsize,
+}
+
+impl CategoryCounters {
+    const fn new() -> Self {
+        Self {
+            bytes: AtomicUsize::new(0),
+            count: AtomicUsize::new(0),
+        }
+    }
+}
+
+// Freeing under another category than the allocation was made with is a
+// bug, but must not wrap the counters around
+fn saturating_sub(counter: &AtomicUsize, value: usize) {
+    let result =
+        counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| {
+            Some(old.saturating_sub(value))
+        });
+    debug_assert!(
+        matches!(result, Ok(old) if old >= value),
+        "allocator counter underflow"
+    );
+}
+
+/// Live bytes and number of live allocations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CategoryStats {
+    pub bytes: usize,
+    pub count: usize,
+}
+
+/// Snapshot of the allocator counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocStats {
+    pub allocated_bytes: usize,
+    pub peak_bytes: usize,
+    pub allocation_count: usize,
+    pub total_allocation_count: usize,
+    pub categories: [CategoryStats; AllocCategory::COUNT],
+}
+
+impl fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "allocated {} bytes in {} blocks (peak {} bytes, {} allocations)",
+            self.allocated_bytes,
+            self.allocation_count,
+            self.peak_bytes,
+            self.total_allocation_count,
+        )?;
+        for category in AllocCategory::ALL {
+            let stats = self.categories[category as usize];
+            writeln!(
+                f,
+                "  {:<8} {:>12} bytes {:>8} blocks",
+                category.get_name(),
+                stats.bytes,
+                stats.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Alloc<A: Allocator> {
     inner: A,
     allocated_bytes: AtomicUsize,
+    limit_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    allocation_count: AtomicUsize,
+    total_allocation_count: AtomicUsize,
+    categories: [CategoryCounters; AllocCategory::COUNT],
 }
 
 impl<A: Allocator> Alloc<A> {
-    const fn new(inner: A) -> Self {
+    pub const fn new(inner: A) -> Self {
         Self {
             inner,
             allocated_bytes: AtomicUsize::new(0),
+            limit_bytes: AtomicUsize::new(usize::MAX),
+            peak_bytes: AtomicUsize::new(0),
+            allocation_count: AtomicUsize::new(0),
+            total_allocation_count: AtomicUsize::new(0),
+            categories: [const { CategoryCounters::new() };
+                AllocCategory::COUNT],
         }
     }
 
     pub fn allocated_bytes(&self) -> usize {
         self.allocated_bytes.load(Ordering::Relaxed)
     }
+
+    pub fn limit_bytes(&self) -> Option<usize> {
+        match self.limit_bytes.load(Ordering::Relaxed) {
+            usize::MAX => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Set the maximum number of allocated bytes, allocations that would
+    /// exceed it fail with `AllocError`.
+    pub fn set_limit_bytes(&self, limit: Option<usize>) {
+        self.limit_bytes
+            .store(limit.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    fn check_limit(&self, size: usize) -> Result<(), AllocError> {
+        let limit = self.limit_bytes.load(Ordering::Relaxed);
+        match self.allocated_bytes().checked_add(size) {
+            Some(bytes) if bytes <= limit => Ok(()),
+            _ => Err(AllocError),
+        }
+    }
+
+    pub fn stats(&self) -> AllocStats {
+        let mut categories = [CategoryStats::default(); AllocCategory::COUNT];
+        for (stats, counters) in categories.iter_mut().zip(&self.categories) {
+            stats.bytes = counters.bytes.load(Ordering::Relaxed);
+            stats.count = counters.count.load(Ordering::Relaxed);
+        }
+        AllocStats {
+            allocated_bytes: self.allocated_bytes(),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            allocation_count: self.allocation_count.load(Ordering::Relaxed),
+            total_allocation_count: self
+                .total_allocation_count
+                .load(Ordering::Relaxed),
+            categories,
+        }
+    }
+
+    /// Attribute the allocations made by `f` on the current thread to
+    /// `category`. Memory must be freed or resized under the same category
+    /// it was allocated with.
+    pub fn with_category<R>(
+        &self,
+        category: AllocCategory,
+        f: impl FnOnce() -> R,
+    ) -> R {
+        let _guard = CategoryGuard::new(category);
+        f()
+    }
+
+    fn current_category(&self) -> &CategoryCounters {
+        &self.categories[CATEGORY.get() as usize]
+    }
+
+    fn add_bytes(&self, size: usize) {
+        let bytes = self.allocated_bytes.fetch_add(size, Ordering::Relaxed);
+        self.peak_bytes.fetch_max(bytes + size, Ordering::Relaxed);
+        self.current_category()
+            .bytes
+            .fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn sub_bytes(&self, size: usize) {
+        saturating_sub(&self.allocated_bytes, size);
+        saturating_sub(&self.current_category().bytes, size);
+    }
+
+    fn on_allocate(&self, layout: Layout) {
+        self.add_bytes(layout.size());
+        self.allocation_count.fetch_add(1, Ordering::Relaxed);
+        self.total_allocation_count.fetch_add(1, Ordering::Relaxed);
+        self.current_category()
+            .count
+            .fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 unsafe impl<A: Allocator> Allocator for Alloc<A> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        self.allocated_bytes
-            .fetch_add(layout.size(), Ordering::Relaxed);
-        self.inner.allocate(layout)
+        self.check_limit(layout.size())?;
+        let result = self.inner.allocate(layout)?;
+        self.on_allocate(layout);
+        Ok(result)
     }
 
     fn allocate_zeroed(
         &self,
         layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        self.allocated_bytes
-            .fetch_add(layout.size(), Ordering::Relaxed);
-        self.inner.allocate_zeroed(layout)
+        self.check_limit(layout.size())?;
+        let result = self.inner.allocate_zeroed(layout)?;
+        self.on_allocate(layout);
+        Ok(result)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        self.allocated_bytes
-            .fetch_sub(layout.size(), Ordering::Relaxed);
+        self.sub_bytes(layout.size());
+        saturating_sub(&self.allocation_count, 1);
+        saturating_sub(&self.current_category().count, 1);
         self.inner.deallocate(ptr, layout)
     }
 
@@ -49,11 +281,10 @@ unsafe impl<A: Allocator> Allocator for Alloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        self.allocated_bytes.fetch_add(
-            new_layout.size().wrapping_sub(old_layout.size()),
-            Ordering::Relaxed,
-        );
-        self.inner.grow(ptr, old_layout, new_layout)
+        self.check_limit(new_layout.size() - old_layout.size())?;
+        let result = self.inner.grow(ptr, old_layout, new_layout)?;
+        self.add_bytes(new_layout.size() - old_layout.size());
+        Ok(result)
     }
 
     unsafe fn grow_zeroed(
@@ -62,11 +293,10 @@ unsafe impl<A: Allocator> Allocator for Alloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        self.allocated_bytes.fetch_add(
-            new_layout.size().wrapping_sub(old_layout.size()),
-            Ordering::Relaxed,
-        );
-        self.inner.grow_zeroed(ptr, old_layout, new_layout)
+        self.check_limit(new_layout.size() - old_layout.size())?;
+        let result = self.inner.grow_zeroed(ptr, old_layout, new_layout)?;
+        self.add_bytes(new_layout.size() - old_layout.size());
+        Ok(result)
     }
 
     unsafe fn shrink(
@@ -75,10 +305,134 @@ unsafe impl<A: Allocator> Allocator for Alloc<A> {
         old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        self.allocated_bytes.fetch_sub(
-            old_layout.size().wrapping_sub(new_layout.size()),
-            Ordering::Relaxed,
+        let result = self.inner.shrink(ptr, old_layout, new_layout)?;
+        self.sub_bytes(old_layout.size() - new_layout.size());
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::Global;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Barrier;
+    use std::thread;
+
+    #[test]
+    fn test_stats() {
+        let alloc = Alloc::new(Global);
+        let small = Layout::array::<u8>(16).unwrap();
+        let large = Layout::array::<u8>(64).unwrap();
+        let other_ptr = alloc.allocate(small).unwrap().cast();
+        let chunk_ptrs =
+            alloc.with_category(AllocCategory::Chunk, || unsafe {
+                let ptr = alloc.allocate(small).unwrap().cast();
+                let ptr = alloc.grow(ptr, small, large).unwrap().cast();
+                (ptr, alloc.allocate(large).unwrap().cast())
+            });
+        unsafe {
+            alloc.deallocate(other_ptr, small);
+        }
+        let stats = alloc.stats();
+        assert_eq!(stats.allocated_bytes, 128);
+        assert_eq!(stats.peak_bytes, 144);
+        assert_eq!(stats.allocation_count, 2);
+        assert_eq!(stats.total_allocation_count, 3);
+        assert_eq!(
+            stats.categories[AllocCategory::Chunk as usize],
+            CategoryStats {
+                bytes: 128,
+                count: 2
+            }
+        );
+        assert_eq!(
+            stats.categories[AllocCategory::Other as usize],
+            CategoryStats::default()
+        );
+        alloc.with_category(AllocCategory::Chunk, || unsafe {
+            alloc.deallocate(chunk_ptrs.0, large);
+            alloc.deallocate(chunk_ptrs.1, large);
+        });
+        assert_eq!(alloc.stats().allocated_bytes, 0);
+    }
+
+    #[test]
+    fn test_limit() {
+        let alloc = Alloc::new(Global);
+        let layout = Layout::array::<u8>(64).unwrap();
+        let larger = Layout::array::<u8>(128).unwrap();
+        alloc.set_limit_bytes(Some(100));
+        assert_eq!(alloc.limit_bytes(), Some(100));
+        let ptr = alloc.allocate(layout).unwrap().cast();
+        assert!(alloc.allocate(layout).is_err());
+        unsafe {
+            assert!(alloc.grow(ptr, layout, larger).is_err());
+        }
+        assert_eq!(alloc.allocated_bytes(), 64);
+        assert_eq!(alloc.stats().allocation_count, 1);
+        alloc.set_limit_bytes(None);
+        unsafe {
+            let ptr = alloc.grow(ptr, layout, larger).unwrap().cast();
+            alloc.deallocate(ptr, larger);
+        }
+    }
+
+    #[test]
+    fn test_category_per_thread() {
+        let alloc = Alloc::new(Global);
+        let layout = Layout::array::<u8>(32).unwrap();
+        let barrier = Barrier::new(2);
+        thread::scope(|scope| {
+            for category in [AllocCategory::String, AllocCategory::List] {
+                let (alloc, barrier) = (&alloc, &barrier);
+                scope.spawn(move || {
+                    alloc.with_category(category, || {
+                        // Both threads are inside `with_category` here
+                        barrier.wait();
+                        let ptr = alloc.allocate(layout).unwrap().cast();
+                        barrier.wait();
+                        unsafe {
+                            alloc.deallocate(ptr, layout);
+                        }
+                    });
+                });
+            }
+        });
+        let stats = alloc.stats();
+        assert_eq!(stats.total_allocation_count, 2);
+        assert_eq!(stats.allocated_bytes, 0);
+        for category in AllocCategory::ALL {
+            assert_eq!(
+                stats.categories[category as usize],
+                CategoryStats::default()
+            );
+        }
+    }
+
+    #[test]
+    fn test_category_restored_on_panic() {
+        let alloc = Alloc::new(Global);
+        let layout = Layout::array::<u8>(8).unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            alloc.with_category(AllocCategory::Map, || panic!());
+        }));
+        assert!(result.is_err());
+        unsafe {
+            let ptr = alloc.allocate(layout).unwrap().cast();
+            assert_eq!(
+                alloc.stats().categories[AllocCategory::Other as usize].count,
+                1
+            );
+            alloc.deallocate(ptr, layout);
+        }
+        assert_eq!(
+            alloc.stats(),
+            AllocStats {
+                total_allocation_count: 1,
+                peak_bytes: 8,
+                ..AllocStats::default()
+            }
         );
-        self.inner.shrink(ptr, old_layout, new_layout)
     }
 }
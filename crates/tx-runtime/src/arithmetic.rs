@@ -0,0 +1,657 @@
+use std::cmp::Ordering;
+
+use crate::{
+    opcodes::*,
+    types::{TxFloat, TxInt},
+    value::{compare_int_float, Value},
+    vm::RuntimeError,
+};
+
+/// Binary arithmetic operators, for ADD to POWER.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithmeticOp {
+    Add,
+    Substract,
+    Multiply,
+    Divide,
+    IntDivide,
+    Modulo,
+    Power,
+}
+
+impl ArithmeticOp {
+    pub const fn from_opcode(opc: OpCode) -> Option<Self> {
+        match opc {
+            ADD => Some(Self::Add),
+            SUBSTRACT => Some(Self::Substract),
+            MULTIPLY => Some(Self::Multiply),
+            DIVIDE => Some(Self::Divide),
+            INT_DIVIDE => Some(Self::IntDivide),
+            MODULO => Some(Self::Modulo),
+            POWER => Some(Self::Power),
+            _ => None,
+        }
+    }
+}
+
+/// Comparison operators, for EQUAL to LESS_EQUAL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonOp {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl ComparisonOp {
+    pub const fn from_opcode(opc: OpCode) -> Option<Self> {
+        match opc {
+            EQUAL => Some(Self::Equal),
+            NOT_EQUAL => Some(Self::NotEqual),
+            GREATER => Some(Self::Greater),
+            GREATER_EQUAL => Some(Self::GreaterEqual),
+            LESS => Some(Self::Less),
+            LESS_EQUAL => Some(Self::LessEqual),
+            _ => None,
+        }
+    }
+}
+
+/// Bitwise and shift operators, for BIT_AND to SHIFT_RIGHT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitwiseOp {
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl BitwiseOp {
+    pub const fn from_opcode(opc: OpCode) -> Option<Self> {
+        match opc {
+            BIT_AND => Some(Self::BitAnd),
+            BIT_OR => Some(Self::BitOr),
+            BIT_XOR => Some(Self::BitXor),
+            SHIFT_LEFT => Some(Self::ShiftLeft),
+            SHIFT_RIGHT => Some(Self::ShiftRight),
+            _ => None,
+        }
+    }
+}
+
+/// What integer arithmetic does when the result does not fit in `TxInt`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+/// Integer builtins with explicit overflow behavior, by Tx name. `%` has
+/// none as it never overflows, `MIN % -1` is 0.
+pub const INT_BUILTINS: &[(&str, ArithmeticOp, Overflow)] = &[
+    ("wrapping_add", ArithmeticOp::Add, Overflow::Wrapping),
+    ("wrapping_sub", ArithmeticOp::Substract, Overflow::Wrapping),
+    ("wrapping_mul", ArithmeticOp::Multiply, Overflow::Wrapping),
+    ("wrapping_div", ArithmeticOp::Divide, Overflow::Wrapping),
+    (
+        "wrapping_int_div",
+        ArithmeticOp::IntDivide,
+        Overflow::Wrapping,
+    ),
+    ("wrapping_pow", ArithmeticOp::Power, Overflow::Wrapping),
+    ("saturating_add", ArithmeticOp::Add, Overflow::Saturating),
+    (
+        "saturating_sub",
+        ArithmeticOp::Substract,
+        Overflow::Saturating,
+    ),
+    (
+        "saturating_mul",
+        ArithmeticOp::Multiply,
+        Overflow::Saturating,
+    ),
+    ("saturating_div", ArithmeticOp::Divide, Overflow::Saturating),
+    (
+        "saturating_int_div",
+        ArithmeticOp::IntDivide,
+        Overflow::Saturating,
+    ),
+    ("saturating_pow", ArithmeticOp::Power, Overflow::Saturating),
+];
+
+/// Negation builtins with explicit overflow behavior, by Tx name.
+pub const INT_NEGATE_BUILTINS: &[(&str, Overflow)] = &[
+    ("wrapping_neg", Overflow::Wrapping),
+    ("saturating_neg", Overflow::Saturating),
+];
+
+type IntOp = fn(TxInt, TxInt) -> TxInt;
+type CheckedIntOp = fn(TxInt, TxInt) -> Option<TxInt>;
+
+/// Integer arithmetic for the binary arithmetic operators. Divide
+/// truncates toward zero, IntDivide rounds toward negative infinity and
+/// Modulo takes the sign of the divisor, so that
+/// `a == (a // b) * b + a % b`. Dividing by zero is an error in every
+/// mode, and so is a negative exponent as the result is not an integer.
+/// `binary_op` promotes to Float for those instead.
+pub fn int_binary_op(
+    op: ArithmeticOp,
+    a: TxInt,
+    b: TxInt,
+    overflow: Overflow,
+) -> Result<TxInt, RuntimeError> {
+    use ArithmeticOp::*;
+    if matches!(op, Divide | IntDivide | Modulo) && b == 0 {
+        return Err(RuntimeError::DivisionByZero);
+    }
+    // Only MIN / -1 overflows, where the remainder is 0
+    let rem = if b == 0 { 0 } else { a.wrapping_rem(b) };
+    let floor_adjust = rem != 0 && (rem < 0) != (b < 0);
+    let (checked, wrapping, saturating): (CheckedIntOp, IntOp, IntOp) =
+        match op {
+            Add => (
+                TxInt::checked_add,
+                TxInt::wrapping_add,
+                TxInt::saturating_add,
+            ),
+            Substract => (
+                TxInt::checked_sub,
+                TxInt::wrapping_sub,
+                TxInt::saturating_sub,
+            ),
+            Multiply => (
+                TxInt::checked_mul,
+                TxInt::wrapping_mul,
+                TxInt::saturating_mul,
+            ),
+            Divide | IntDivide => (
+                TxInt::checked_div,
+                TxInt::wrapping_div,
+                TxInt::saturating_div,
+            ),
+            Modulo => return Ok(if floor_adjust { rem + b } else { rem }),
+            Power => return int_power(a, b, overflow),
+        };
+    let result = match overflow {
+        Overflow::Checked => checked(a, b),
+        Overflow::Wrapping => Some(wrapping(a, b)),
+        Overflow::Saturating => Some(saturating(a, b)),
+    };
+    // The truncated quotient is one too large when the remainder is
+    // adjusted, it can not be MIN then so this never overflows.
+    match result {
+        Some(q) if op == IntDivide && floor_adjust => Ok(q - 1),
+        Some(q) => Ok(q),
+        None => Err(RuntimeError::IntegerOverflow),
+    }
+}
+
+/// Integer negation, only `-MIN` overflows.
+pub fn int_negate(
+    a: TxInt,
+    overflow: Overflow,
+) -> Result<TxInt, RuntimeError> {
+    match overflow {
+        Overflow::Checked => {
+            a.checked_neg().ok_or(RuntimeError::IntegerOverflow)
+        }
+        Overflow::Wrapping => Ok(a.wrapping_neg()),
+        Overflow::Saturating => Ok(a.saturating_neg()),
+    }
+}
+
+fn int_power(
+    a: TxInt,
+    b: TxInt,
+    overflow: Overflow,
+) -> Result<TxInt, RuntimeError> {
+    if b < 0 {
+        return Err(RuntimeError::NegativeExponent);
+    }
+    // Exponents too large for u32 keep their parity for bases -1, 0, 1
+    // and overflow for every other base anyway.
+    let exp = u32::try_from(b).unwrap_or(u32::MAX - 1 + (b & 1) as u32);
+    let result = match overflow {
+        Overflow::Checked => a.checked_pow(exp),
+        Overflow::Wrapping => Some(a.wrapping_pow(exp)),
+        Overflow::Saturating => Some(a.saturating_pow(exp)),
+    };
+    result.ok_or(RuntimeError::IntegerOverflow)
+}
+
+/// Float arithmetic, following IEEE 754 for division by zero and NaN.
+pub fn float_binary_op(op: ArithmeticOp, a: TxFloat, b: TxFloat) -> TxFloat {
+    match op {
+        ArithmeticOp::Add => a + b,
+        ArithmeticOp::Substract => a - b,
+        ArithmeticOp::Multiply => a * b,
+        ArithmeticOp::Divide => a / b,
+        ArithmeticOp::IntDivide => float_div_mod(a, b).0,
+        ArithmeticOp::Modulo => float_div_mod(a, b).1,
+        ArithmeticOp::Power => a.powf(b),
+    }
+}
+
+// Floored quotient and remainder. The quotient is derived from the exact
+// remainder of fmod so that `(a // b) * b + a % b == a` up to rounding,
+// `(a / b).floor()` can be off by one (`1.0 // 0.1` would be 10.0).
+fn float_div_mod(a: TxFloat, b: TxFloat) -> (TxFloat, TxFloat) {
+    let mut rem = a % b;
+    if rem.is_nan() {
+        // Division by zero or infinite dividend, IEEE 754 results
+        return ((a / b).floor(), rem);
+    }
+    let mut quotient = (a - rem) / b;
+    if rem != 0.0 && (rem < 0.0) != (b < 0.0) {
+        rem += b;
+        quotient -= 1.0;
+    }
+    (quotient.round(), rem)
+}
+
+/// Evaluate a binary arithmetic operator as executed by the VM.
+///
+/// Two Ints give an Int, except for a negative exponent that gives a
+/// Float. When one operand is a Float the other is converted and the
+/// result is a Float.
+pub fn binary_op(
+    op: ArithmeticOp,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value, RuntimeError> {
+    let (a, b) = match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => {
+            if op == ArithmeticOp::Power && b < 0 {
+                (a as TxFloat, b as TxFloat)
+            } else {
+                return int_binary_op(op, a, b, Overflow::Checked)
+                    .map(Value::Int);
+            }
+        }
+        (Value::Float(a), Value::Float(b)) => (a, b),
+        (Value::Int(a), Value::Float(b)) => (a as TxFloat, b),
+        (Value::Float(a), Value::Int(b)) => (a, b as TxFloat),
+        _ => return Err(RuntimeError::OperandsMustBeNumbers),
+    };
+    Ok(Value::Float(float_binary_op(op, a, b)))
+}
+
+/// Evaluate a comparison operator as executed by the VM. Numbers of both
+/// types compare by value, consistently with `==`. Any comparison with NaN
+/// is false, except `!=`.
+pub fn comparison_op(
+    op: ComparisonOp,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value, RuntimeError> {
+    let is_match: fn(Ordering) -> bool = match op {
+        ComparisonOp::Equal => return Ok(Value::Bool(lhs == rhs)),
+        ComparisonOp::NotEqual => return Ok(Value::Bool(lhs != rhs)),
+        ComparisonOp::Greater => Ordering::is_gt,
+        ComparisonOp::GreaterEqual => Ordering::is_ge,
+        ComparisonOp::Less => Ordering::is_lt,
+        ComparisonOp::LessEqual => Ordering::is_le,
+    };
+    let ordering = match (lhs, rhs) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(&b),
+        (Value::Int(i), Value::Float(f)) => compare_int_float(i, f),
+        (Value::Float(f), Value::Int(i)) => {
+            compare_int_float(i, f).map(Ordering::reverse)
+        }
+        // Chars are ordered by code point
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(&b)),
+        _ => return Err(RuntimeError::OperandsNotComparable),
+    };
+    let result = ordering.is_some_and(is_match);
+    Ok(Value::Bool(result))
+}
+
+pub fn negate(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(i) => int_negate(i, Overflow::Checked).map(Value::Int),
+        Value::Float(f) => Ok(Value::Float(-f)),
+        _ => Err(RuntimeError::OperandMustBeNumber),
+    }
+}
+
+/// Evaluate a bitwise or shift operator as executed by the VM. Shifts by
+/// `TxInt::BITS` or more shift every bit out: `<<` gives 0 and `>>`, that
+/// is arithmetic, gives 0 or -1 depending on the sign. Bits shifted out to
+/// the left are discarded, it is not an overflow. A negative shift count
+/// is an error.
+pub fn bitwise_op(
+    op: BitwiseOp,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value, RuntimeError> {
+    let (Value::Int(a), Value::Int(b)) = (lhs, rhs) else {
+        return Err(RuntimeError::OperandsMustBeIntegers);
+    };
+    let count = || {
+        if b < 0 {
+            Err(RuntimeError::NegativeShiftCount)
+        } else {
+            Ok(u32::try_from(b).unwrap_or(u32::MAX))
+        }
+    };
+    let result = match op {
+        BitwiseOp::BitAnd => a & b,
+        BitwiseOp::BitOr => a | b,
+        BitwiseOp::BitXor => a ^ b,
+        BitwiseOp::ShiftLeft => a.checked_shl(count()?).unwrap_or(0),
+        BitwiseOp::ShiftRight => {
+            a.checked_shr(count()?)
+                .unwrap_or(if a < 0 { -1 } else { 0 })
+        }
+    };
+    Ok(Value::Int(result))
+}
+
+pub fn bit_not(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(i) => Ok(Value::Int(!i)),
+        _ => Err(RuntimeError::OperandMustBeInteger),
+    }
+}
+
+pub type ConversionBuiltin = fn(Value) -> Result<Value, RuntimeError>;
+
+/// Conversion and rounding builtins, by Tx name.
+pub const CONVERSION_BUILTINS: &[(&str, ConversionBuiltin)] = &[
+    ("int", to_int),
+    ("float", to_float),
+    ("char", to_char),
+    ("floor", |value| round_with(value, TxFloat::floor)),
+    ("ceil", |value| round_with(value, TxFloat::ceil)),
+    ("round", |value| round_with(value, TxFloat::round)),
+    ("trunc", |value| round_with(value, TxFloat::trunc)),
+];
+
+/// Convert to Int, truncating Floats toward zero. NaN, infinities and
+/// Floats out of the range of `TxInt` are an error rather than saturating.
+/// Chars convert to their code point.
+pub fn to_int(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(_) => Ok(value),
+        Value::Char(c) => Ok(Value::Int(u32::from(c) as TxInt)),
+        Value::Float(f) => {
+            let truncated = f.trunc();
+            if truncated >= TxInt::MIN as TxFloat
+                && truncated < TxInt::MAX as TxFloat
+            {
+                Ok(Value::Int(truncated as TxInt))
+            } else {
+                Err(RuntimeError::IntegerOverflow)
+            }
+        }
+        _ => Err(RuntimeError::OperandMustBeNumber),
+    }
+}
+
+/// Convert to Float, rounding to the nearest Float for large Ints.
+pub fn to_float(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(i) => Ok(Value::Float(i as TxFloat)),
+        Value::Float(_) => Ok(value),
+        _ => Err(RuntimeError::OperandMustBeNumber),
+    }
+}
+
+/// Convert a code point to a Char. Surrogates and values above U+10FFFF
+/// are not Unicode scalar values and are an error.
+pub fn to_char(value: Value) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(i) => u32::try_from(i)
+            .ok()
+            .and_then(char::from_u32)
+            .map(Value::Char)
+            .ok_or(RuntimeError::InvalidCodePoint),
+        Value::Char(_) => Ok(value),
+        _ => Err(RuntimeError::OperandMustBeInteger),
+    }
+}
+
+// Rounding keeps the type, Ints are already integral.
+fn round_with(
+    value: Value,
+    round: fn(TxFloat) -> TxFloat,
+) -> Result<Value, RuntimeError> {
+    match value {
+        Value::Int(_) => Ok(value),
+        Value::Float(f) => Ok(Value::Float(round(f))),
+        _ => Err(RuntimeError::OperandMustBeNumber),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArithmeticOp::*, BitwiseOp::*, ComparisonOp::*, *};
+
+    #[test]
+    fn test_checked() {
+        let int = |opc, a, b| binary_op(opc, Value::Int(a), Value::Int(b));
+        assert_eq!(int(Substract, 1, 3), Ok(Value::Int(-2)));
+        assert_eq!(int(Add, 1, 0), Ok(Value::Int(1)));
+        assert_eq!(int(Divide, -7, 2), Ok(Value::Int(-3)));
+        assert_eq!(
+            int(Add, TxInt::MAX, 1),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(
+            int(Divide, TxInt::MIN, -1),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(int(Divide, 1, 0), Err(RuntimeError::DivisionByZero));
+        assert_eq!(
+            negate(Value::Int(TxInt::MIN)),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(
+            binary_op(Divide, Value::Float(1.0), Value::Float(0.0)),
+            Ok(Value::Float(TxFloat::INFINITY))
+        );
+        assert_eq!(
+            binary_op(Add, Value::Nil, Value::Int(1)),
+            Err(RuntimeError::OperandsMustBeNumbers)
+        );
+    }
+
+    #[test]
+    fn test_wrapping_saturating() {
+        use Overflow::*;
+        assert_eq!(
+            int_binary_op(Add, TxInt::MAX, 1, Wrapping),
+            Ok(TxInt::MIN)
+        );
+        assert_eq!(
+            int_binary_op(Multiply, TxInt::MIN, 2, Saturating),
+            Ok(TxInt::MIN)
+        );
+        assert_eq!(
+            int_binary_op(Divide, TxInt::MIN, -1, Saturating),
+            Ok(TxInt::MAX)
+        );
+        assert_eq!(
+            int_binary_op(Divide, 1, 0, Wrapping),
+            Err(RuntimeError::DivisionByZero)
+        );
+        assert_eq!(int_negate(TxInt::MIN, Wrapping), Ok(TxInt::MIN));
+        assert_eq!(int_negate(TxInt::MIN, Saturating), Ok(TxInt::MAX));
+        assert_eq!(int_negate(5, Saturating), Ok(-5));
+        assert!(INT_BUILTINS
+            .iter()
+            .any(|&(name, _, _)| name == "saturating_sub"));
+        assert!(INT_NEGATE_BUILTINS
+            .iter()
+            .any(|&(name, _)| name == "wrapping_neg"));
+    }
+
+    #[test]
+    fn test_int_division_modulo_power() {
+        let int = |opc, a, b| binary_op(opc, Value::Int(a), Value::Int(b));
+        assert_eq!(int(IntDivide, -7, 2), Ok(Value::Int(-4)));
+        assert_eq!(int(IntDivide, 7, -2), Ok(Value::Int(-4)));
+        assert_eq!(int(IntDivide, -8, 2), Ok(Value::Int(-4)));
+        assert_eq!(int(Modulo, -7, 2), Ok(Value::Int(1)));
+        assert_eq!(int(Modulo, 7, -2), Ok(Value::Int(-1)));
+        assert_eq!(int(Modulo, TxInt::MIN, -1), Ok(Value::Int(0)));
+        assert_eq!(
+            int(IntDivide, TxInt::MIN, -1),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(int(Modulo, 1, 0), Err(RuntimeError::DivisionByZero));
+        assert_eq!(int(Power, 3, 4), Ok(Value::Int(81)));
+        assert_eq!(int(Power, 2, -1), Ok(Value::Float(0.5)));
+        assert_eq!(
+            int_binary_op(Power, 2, -1, Overflow::Wrapping),
+            Err(RuntimeError::NegativeExponent)
+        );
+        assert_eq!(int(Power, -1, TxInt::MAX), Ok(Value::Int(-1)));
+        assert_eq!(int(Power, 2, 200), Err(RuntimeError::IntegerOverflow));
+        assert_eq!(
+            int_binary_op(Power, 2, 200, Overflow::Saturating),
+            Ok(TxInt::MAX)
+        );
+        assert_eq!(
+            int_binary_op(IntDivide, TxInt::MIN, -1, Overflow::Wrapping),
+            Ok(TxInt::MIN)
+        );
+    }
+
+    #[test]
+    fn test_mixed_operands() {
+        assert_eq!(
+            binary_op(Add, Value::Int(1), Value::Float(0.5)),
+            Ok(Value::Float(1.5))
+        );
+        assert_eq!(
+            binary_op(Modulo, Value::Float(-7.0), Value::Int(2)),
+            Ok(Value::Float(1.0))
+        );
+        assert_eq!(
+            binary_op(IntDivide, Value::Float(7.0), Value::Float(-2.0)),
+            Ok(Value::Float(-4.0))
+        );
+        let float = |op, a, b| float_binary_op(op, a, b);
+        assert_eq!(float(IntDivide, 1.0, 0.1), 9.0);
+        assert_eq!(float(IntDivide, -1.0, 0.1), -10.0);
+        assert!(float(Modulo, 1.0, 0.1) > 0.09);
+        assert_eq!(float(IntDivide, 1.0, 0.0), TxFloat::INFINITY);
+        let cmp = |opc, lhs, rhs| comparison_op(opc, lhs, rhs);
+        let big = Value::Int(TxInt::MAX);
+        let big_float = Value::Float(TxInt::MAX as TxFloat);
+        assert_eq!(cmp(Equal, big, big_float), Ok(Value::Bool(false)));
+        assert_eq!(cmp(Less, big, big_float), Ok(Value::Bool(true)));
+        assert_eq!(cmp(Greater, big_float, big), Ok(Value::Bool(true)));
+        let one = Value::Int(1);
+        assert_eq!(cmp(Equal, one, Value::Float(1.0)), Ok(Value::Bool(true)));
+        assert_eq!(
+            cmp(LessEqual, one, Value::Float(1.0)),
+            Ok(Value::Bool(true))
+        );
+        let nan = Value::Float(TxFloat::NAN);
+        assert_eq!(cmp(GreaterEqual, one, nan), Ok(Value::Bool(false)));
+        assert_eq!(cmp(NotEqual, one, nan), Ok(Value::Bool(true)));
+        assert_eq!(
+            cmp(Less, Value::Nil, one),
+            Err(RuntimeError::OperandsNotComparable)
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(to_int(Value::Float(-2.7)), Ok(Value::Int(-2)));
+        assert_eq!(
+            to_int(Value::Float(TxFloat::NAN)),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(
+            to_int(Value::Float(TxInt::MAX as TxFloat)),
+            Err(RuntimeError::IntegerOverflow)
+        );
+        assert_eq!(to_float(Value::Int(3)), Ok(Value::Float(3.0)));
+        assert_eq!(
+            to_float(Value::Bool(true)),
+            Err(RuntimeError::OperandMustBeNumber)
+        );
+        let builtin = |name| {
+            CONVERSION_BUILTINS
+                .iter()
+                .find(|&&(builtin, _)| builtin == name)
+                .unwrap()
+                .1
+        };
+        assert_eq!(
+            builtin("round")(Value::Float(-2.5)),
+            Ok(Value::Float(-3.0))
+        );
+        assert_eq!(
+            builtin("floor")(Value::Float(-2.5)),
+            Ok(Value::Float(-3.0))
+        );
+        assert_eq!(builtin("ceil")(Value::Int(7)), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let int = |opc, a, b| bitwise_op(opc, Value::Int(a), Value::Int(b));
+        assert_eq!(int(BitAnd, 0b1100, 0b1010), Ok(Value::Int(0b1000)));
+        assert_eq!(int(BitOr, 0b1100, 0b1010), Ok(Value::Int(0b1110)));
+        assert_eq!(int(BitXor, 0b1100, 0b1010), Ok(Value::Int(0b0110)));
+        assert_eq!(bit_not(Value::Int(0)), Ok(Value::Int(-1)));
+        let bits = TxInt::BITS as TxInt;
+        assert_eq!(int(ShiftLeft, 1, 4), Ok(Value::Int(16)));
+        assert_eq!(int(ShiftLeft, -1, bits - 1), Ok(Value::Int(TxInt::MIN)));
+        assert_eq!(int(ShiftLeft, 1, bits), Ok(Value::Int(0)));
+        assert_eq!(int(ShiftRight, -16, 2), Ok(Value::Int(-4)));
+        assert_eq!(int(ShiftRight, -16, bits), Ok(Value::Int(-1)));
+        assert_eq!(int(ShiftRight, 16, TxInt::MAX), Ok(Value::Int(0)));
+        assert_eq!(
+            int(ShiftRight, 16, -1),
+            Err(RuntimeError::NegativeShiftCount)
+        );
+        assert_eq!(
+            bitwise_op(BitAnd, Value::Float(1.0), Value::Int(1)),
+            Err(RuntimeError::OperandsMustBeIntegers)
+        );
+        assert_eq!(
+            bit_not(Value::Bool(true)),
+            Err(RuntimeError::OperandMustBeInteger)
+        );
+    }
+
+    #[test]
+    fn test_chars() {
+        let smiley = Value::Char('\u{1F600}');
+        assert_eq!(to_int(smiley), Ok(Value::Int(0x1F600)));
+        assert_eq!(to_char(Value::Int(0x1F600)), Ok(smiley));
+        assert_eq!(to_char(Value::Int(0x61)), Ok(Value::Char('a')));
+        for invalid in [-1, 0xD800, 0x11_0000] {
+            assert_eq!(
+                to_char(Value::Int(invalid)),
+                Err(RuntimeError::InvalidCodePoint)
+            );
+        }
+        assert_eq!(
+            comparison_op(Less, Value::Char('Z'), Value::Char('a')),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            comparison_op(Equal, Value::Char('a'), Value::Int(0x61)),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            comparison_op(Less, Value::Char('a'), Value::Int(0x61)),
+            Err(RuntimeError::OperandsNotComparable)
+        );
+        assert_eq!(
+            binary_op(Add, Value::Char('a'), Value::Int(1)),
+            Err(RuntimeError::OperandsMustBeNumbers)
+        );
+    }
+}
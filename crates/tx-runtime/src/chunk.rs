@@ -1,87 +1,198 @@
 use crate::{
+    allocator::AllocCategory,
     dyn_array::DynArray,
+    hash_map::HashMap,
     opcodes::OpCode,
-    value::Value,
-    vm::{VmAlloc, VM},
+    value::{Value, ValueBits},
+    vm::{RuntimeError, VmAlloc, VM},
 };
 
-pub struct LineStart {
-    offset: usize,
-    line: usize,
+/// Source location of an instruction. Lines and columns start at 1 and
+/// columns and lengths are counted in characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub file_id: u32,
+    pub line: u32,
+    pub column: u32,
+    pub len: u32,
 }
 
+// A span applies to all the bytecode from its offset until the offset of
+// the next one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SpanStart {
+    pub(crate) offset: usize,
+    pub(crate) span: Span,
+}
+
+// Longest encoded span entry, 10 bytes per 64 bits varint and 5 for the
+// length
+const MAX_SPAN_ENTRY_LEN: usize = 35;
+
 pub struct Chunk<'a> {
     pub bytecode: DynArray<'a, u8, VmAlloc>,
     pub constants: DynArray<'a, Value, VmAlloc>,
-    pub lines: DynArray<'a, LineStart, VmAlloc>,
+    // Run-length and delta encoded span table, see `encode_span_start`
+    pub spans: DynArray<'a, u8, VmAlloc>,
+    // Shared by all the spans of the chunk
+    file_id: u32,
+    // Base of the deltas of the next span entry
+    last_span_start: Option<SpanStart>,
+    // Index of each value in `constants`, for O(1) deduplication
+    constant_indices: HashMap<'a, ValueBits, usize, VmAlloc>,
 }
 
 impl<'a> Chunk<'a> {
-    fn write_line(&mut self, tvm: &mut VM, line: usize) {
-        if let Some(last) = self.lines.last() {
-            if last.line != line {
-                unsafe {
-                    self.lines.push(
-                        &tvm.allocator,
-                        LineStart {
-                            offset: self.bytecode.len(),
-                            line,
-                        },
-                    );
-                }
-            }
+    pub fn new(tvm: &'a VM) -> Self {
+        Self {
+            bytecode: DynArray::new(&tvm.allocator),
+            constants: DynArray::new(&tvm.allocator),
+            spans: DynArray::new(&tvm.allocator),
+            file_id: 0,
+            last_span_start: None,
+            constant_indices: HashMap::new(&tvm.allocator),
         }
     }
 
-    pub fn get_line(&self, offset: usize) -> usize {
-        let idx = match self
-            .lines
-            .binary_search_by(|line_start| line_start.offset.cmp(&offset))
-        {
-            Ok(idx) => idx,
-            Err(idx) => idx - 1,
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::Chunk, || {
+            self.bytecode.destroy(&tvm.allocator);
+            self.constants.destroy(&tvm.allocator);
+            self.spans.destroy(&tvm.allocator);
+            self.constant_indices.destroy(&tvm.allocator);
+        });
+    }
+
+    fn write_span(
+        &mut self,
+        tvm: &VM,
+        span: Span,
+    ) -> Result<(), RuntimeError> {
+        if self.last_span_start.map(|last| last.span) == Some(span) {
+            return Ok(());
+        }
+        if self.last_span_start.is_none() {
+            self.file_id = span.file_id;
+        }
+        debug_assert_eq!(span.file_id, self.file_id);
+        let span_start = SpanStart {
+            offset: self.bytecode.len(),
+            span,
         };
-        self.lines[idx].line
+        let mut entry = [0; MAX_SPAN_ENTRY_LEN];
+        let mut len = 0;
+        encode_span_start(self.last_span_start, span_start, |byte| {
+            entry[len] = byte;
+            len += 1;
+        });
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                self.spans
+                    .try_extend_from_slice(&tvm.allocator, &entry[..len])
+            })?;
+        self.last_span_start = Some(span_start);
+        Ok(())
     }
 
-    pub fn write_constant(&mut self, tvm: &mut VM, value: Value) -> usize {
-        match self.constants.iter().position(|&val| val == value) {
-            Some(idx) => idx,
-            None => {
-                unsafe {
-                    self.constants.push(&tvm.allocator, value);
-                }
-                self.constants.len() - 1
+    pub(crate) fn span_starts(&self) -> impl Iterator<Item = SpanStart> + '_ {
+        let mut bytes = &self.spans[..];
+        let mut last = None;
+        std::iter::from_fn(move || {
+            if bytes.is_empty() {
+                return None;
             }
+            let mut span_start = decode_span_start(last, &mut bytes);
+            span_start.span.file_id = self.file_id;
+            last = Some(span_start);
+            Some(span_start)
+        })
+    }
+
+    /// Replace the span table, used when the bytecode is rewritten.
+    pub(crate) fn set_span_starts(&mut self, tvm: &VM, spans: &[SpanStart]) {
+        let mut bytes = Vec::new();
+        let mut last = None;
+        for &span_start in spans {
+            encode_span_start(last, span_start, |byte| bytes.push(byte));
+            last = Some(span_start);
         }
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                self.spans.resize(&tvm.allocator, 0, 0);
+                self.spans.extend_from_slice(&tvm.allocator, &bytes);
+            });
+        self.last_span_start = last;
     }
 
-    fn write_byte(&mut self, tvm: &mut VM, byte: u8) {
-        unsafe {
-            self.bytecode.push(&tvm.allocator, byte);
+    /// Span of the instruction at `offset`. The table is decoded from the
+    /// start, this is meant for error reporting and debuggers.
+    pub fn get_span(&self, offset: usize) -> Span {
+        self.span_starts()
+            .take_while(|span_start| span_start.offset <= offset)
+            .last()
+            .map_or_else(Span::default, |span_start| span_start.span)
+    }
+
+    pub fn get_line(&self, offset: usize) -> usize {
+        self.get_span(offset).line as usize
+    }
+
+    pub fn write_constant(
+        &mut self,
+        tvm: &VM,
+        value: Value,
+    ) -> Result<usize, RuntimeError> {
+        let key = value.to_bits();
+        if let Some(&idx) = self.constant_indices.get(&key) {
+            return Ok(idx);
         }
+        let idx = self.constants.len();
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                self.constants.try_push(&tvm.allocator, value)?;
+                let result =
+                    self.constant_indices.try_set(&tvm.allocator, key, idx);
+                if result.is_err() {
+                    self.constants.pop();
+                }
+                result
+            })?;
+        Ok(idx)
+    }
+
+    fn write_byte(&mut self, tvm: &VM, byte: u8) -> Result<(), RuntimeError> {
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                self.bytecode.try_push(&tvm.allocator, byte)
+            })?;
+        Ok(())
     }
 
     pub fn write_instruction<const N: usize>(
         &mut self,
-        tvm: &mut VM,
-        line: usize,
+        tvm: &VM,
+        span: Span,
         opc: OpCode,
         operand: usize,
-    ) {
-        self.write_line(tvm, line);
-        unsafe {
-            self.bytecode.reserve(&tvm.allocator, 1 + N);
-            self.bytecode.push(&tvm.allocator, opc.into());
-        }
-        let len = self.bytecode.len();
-        unsafe {
-            self.bytecode.resize(&tvm.allocator, len + N, 0xff);
-        }
+    ) -> Result<(), RuntimeError> {
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                self.bytecode.try_reserve(&tvm.allocator, 1 + N)
+            })?;
+        // Only after the reserve, a span must not outlive a failed write
+        self.write_span(tvm, span)?;
+        let len = self.bytecode.len() + 1;
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || unsafe {
+                // Can not allocate after the reserve
+                self.bytecode.push(&tvm.allocator, opc.into());
+                self.bytecode.resize(&tvm.allocator, len + N, 0xff);
+            });
         write_multibyte_operand::<N>(
             &mut self.bytecode[len..len + N],
             operand,
         );
+        Ok(())
     }
 }
 
@@ -92,3 +203,210 @@ fn write_multibyte_operand<const N: usize>(slice: &mut [u8], operand: usize) {
         slice[i] = ((operand >> (i * 8)) & 0xff) as u8;
     }
 }
+
+pub(crate) fn read_multibyte_operand(slice: &[u8]) -> usize {
+    slice
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &byte)| acc | (usize::from(byte) << (i * 8)))
+}
+
+fn write_varint(mut value: u64, write: &mut impl FnMut(u8)) {
+    while value >= 0x80 {
+        write((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    write(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[0];
+        *bytes = &bytes[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Zigzag encoding keeps small negative deltas small
+fn write_delta(old: u32, new: u32, write: &mut impl FnMut(u8)) {
+    let delta = i64::from(new) - i64::from(old);
+    write_varint(((delta << 1) ^ (delta >> 63)) as u64, write);
+}
+
+fn read_delta(old: u32, bytes: &mut &[u8]) -> u32 {
+    let zigzag = read_varint(bytes);
+    let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+    (i64::from(old) + delta) as u32
+}
+
+// An entry holds the offset, line and column as differences from the
+// previous entry and the length, as varints. Most entries take 4 bytes.
+// The file id is stored once in the chunk.
+fn encode_span_start(
+    last: Option<SpanStart>,
+    span_start: SpanStart,
+    mut write: impl FnMut(u8),
+) {
+    let last = last.unwrap_or_default();
+    debug_assert!(span_start.offset >= last.offset);
+    write_varint((span_start.offset - last.offset) as u64, &mut write);
+    write_delta(last.span.line, span_start.span.line, &mut write);
+    write_delta(last.span.column, span_start.span.column, &mut write);
+    write_varint(span_start.span.len.into(), &mut write);
+}
+
+fn decode_span_start(last: Option<SpanStart>, bytes: &mut &[u8]) -> SpanStart {
+    let last = last.unwrap_or_default();
+    SpanStart {
+        offset: last.offset + read_varint(bytes) as usize,
+        span: Span {
+            file_id: 0,
+            line: read_delta(last.span.line, bytes),
+            column: read_delta(last.span.column, bytes),
+            len: read_varint(bytes) as u32,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+
+    #[test]
+    fn test_write_constant_dedup() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        let values = [
+            Value::Int(0),
+            Value::Float(0.0),
+            Value::Float(-0.0),
+            Value::Float(f64::NAN as _),
+            Value::Bool(false),
+            Value::Nil,
+        ];
+        for (i, &value) in values.iter().enumerate() {
+            assert_eq!(chunk.write_constant(&tvm, value), Ok(i));
+        }
+        for (i, &value) in values.iter().enumerate() {
+            assert_eq!(chunk.write_constant(&tvm, value), Ok(i));
+        }
+        assert_eq!(chunk.constants.len(), values.len());
+        let stats = tvm.alloc_stats();
+        let chunk_stats = stats.categories[AllocCategory::Chunk as usize];
+        assert_ne!(chunk_stats.bytes, 0);
+        assert_eq!(chunk_stats.bytes, stats.allocated_bytes);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+        assert_eq!(tvm.alloc_stats().allocated_bytes, 0);
+    }
+
+    #[test]
+    fn test_write_constant_none() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        assert_eq!(chunk.write_constant(&tvm, Value::None), Ok(0));
+        assert_eq!(chunk.write_constant(&tvm, Value::None), Ok(0));
+        for i in 1..20 {
+            assert_eq!(chunk.write_constant(&tvm, Value::Int(i)), Ok(i as _));
+        }
+        assert_eq!(chunk.write_constant(&tvm, Value::None), Ok(0));
+        assert_eq!(chunk.write_constant(&tvm, Value::Int(7)), Ok(7));
+        assert_eq!(chunk.constants.len(), 20);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        let span = |line, column| Span {
+            file_id: 0,
+            line,
+            column,
+            len: 1,
+        };
+        chunk
+            .write_instruction::<0>(&tvm, span(1, 1), NIL, 0)
+            .unwrap();
+        chunk
+            .write_instruction::<0>(&tvm, span(1, 1), NIL, 0)
+            .unwrap();
+        chunk
+            .write_instruction::<1>(&tvm, span(1, 5), GET_LOCAL, 0)
+            .unwrap();
+        chunk
+            .write_instruction::<0>(&tvm, span(2, 1), RETURN, 0)
+            .unwrap();
+        assert_eq!(chunk.span_starts().count(), 3);
+        assert_eq!(chunk.get_span(0), span(1, 1));
+        assert_eq!(chunk.get_span(1), span(1, 1));
+        assert_eq!(chunk.get_span(2), span(1, 5));
+        assert_eq!(chunk.get_span(3), span(1, 5));
+        assert_eq!(chunk.get_line(4), 2);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+
+    #[test]
+    fn test_write_instruction_out_of_memory() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        let span = Span {
+            file_id: 0,
+            line: 1,
+            column: 1,
+            len: 1,
+        };
+        // Room for the first allocation of one of the arrays only
+        tvm.allocator.set_limit_bytes(Some(8));
+        assert_eq!(
+            chunk.write_instruction::<0>(&tvm, span, NIL, 0),
+            Err(RuntimeError::OutOfMemory)
+        );
+        assert!(chunk.bytecode.is_empty());
+        assert_eq!(chunk.span_starts().count(), 0);
+        tvm.allocator.set_limit_bytes(None);
+        chunk.write_instruction::<0>(&tvm, span, NIL, 0).unwrap();
+        assert_eq!(chunk.span_starts().count(), 1);
+        assert_eq!(chunk.get_span(0), span);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+
+    #[test]
+    fn test_spans_compact() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        let span = |i: u32| Span {
+            file_id: 3,
+            line: 1000 + i / 10,
+            column: 1 + 4 * (i % 10),
+            len: 1 + i % 3,
+        };
+        for i in 0..200 {
+            chunk
+                .write_instruction::<1>(&tvm, span(i), GET_LOCAL, 0)
+                .unwrap();
+        }
+        assert_eq!(chunk.span_starts().count(), 200);
+        assert!(chunk.spans.len() <= 4 * 200 + 2);
+        for i in 0..200 {
+            assert_eq!(chunk.get_span(2 * i as usize + 1), span(i));
+        }
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+}
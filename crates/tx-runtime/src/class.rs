@@ -0,0 +1,598 @@
+use std::ptr;
+
+use crate::{
+    list::TxList,
+    map::TxMap,
+    object::{new_object, ObjRef, Object},
+    value::Value,
+    vm::{RuntimeError, VM},
+};
+
+/// Name of the method called on new instances when the class is called.
+pub const INITIALIZER_NAME: &str = "init";
+
+/// Class created by CLASS, with its methods by name. Methods are closures
+/// that take the instance in the slot of the callee.
+pub struct TxClass<'a> {
+    name: Value,
+    methods: TxMap<'a>,
+    initializer: Option<Value>,
+    superclass: Option<ObjRef>,
+    traits: TxList<'a>,
+}
+
+impl<'a> TxClass<'a> {
+    pub fn new(tvm: &'a VM, name: Value) -> Self {
+        Self {
+            name,
+            methods: TxMap::new(tvm),
+            initializer: None,
+            superclass: None,
+            traits: TxList::new(tvm),
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        self.methods.destroy(tvm);
+        self.traits.destroy(tvm);
+    }
+
+    pub fn get_name(&self) -> Value {
+        self.name
+    }
+
+    /// METHOD, defines or overrides a method.
+    pub fn add_method(
+        &mut self,
+        tvm: &VM,
+        name: Value,
+        method: Value,
+    ) -> Result<(), RuntimeError> {
+        self.methods.set(tvm, name, method)?;
+        if is_string(name, INITIALIZER_NAME) {
+            self.initializer = Some(method);
+        }
+        Ok(())
+    }
+
+    pub fn find_method(&self, name: Value) -> Option<Value> {
+        self.methods.get(name).ok()
+    }
+
+    /// The `init` method, cached as it runs for every instance created.
+    pub fn get_initializer(&self) -> Option<Value> {
+        self.initializer
+    }
+
+    pub fn get_superclass(&self) -> Option<&TxClass<'_>> {
+        self.superclass
+            .as_ref()
+            .map(|superclass| match superclass.get() {
+                Object::Class(class) => class,
+                _ => unreachable!("superclass is not a class"),
+            })
+    }
+
+    /// Whether `self` is `class` or one of its subclasses.
+    pub fn is_subclass_of(&self, class: &TxClass) -> bool {
+        let mut current = Some(self);
+        while let Some(candidate) = current {
+            if ptr::eq(candidate, class) {
+                return true;
+            }
+            current = candidate.get_superclass();
+        }
+        false
+    }
+}
+
+/// Trait created by TRAIT. METHOD adds default methods, or with `nil` as
+/// the method, names that implementing classes must define.
+pub struct TxTrait<'a> {
+    name: Value,
+    methods: TxMap<'a>,
+}
+
+impl<'a> TxTrait<'a> {
+    pub fn new(tvm: &'a VM, name: Value) -> Self {
+        Self {
+            name,
+            methods: TxMap::new(tvm),
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        self.methods.destroy(tvm);
+    }
+
+    pub fn get_name(&self) -> Value {
+        self.name
+    }
+
+    pub fn add_method(
+        &mut self,
+        tvm: &VM,
+        name: Value,
+        method: Value,
+    ) -> Result<(), RuntimeError> {
+        self.methods.set(tvm, name, method)
+    }
+
+    /// Names of the methods without a default body.
+    pub fn required_methods(&self) -> impl Iterator<Item = Value> + '_ {
+        self.methods
+            .iter()
+            .filter(|(_, method)| *method == Value::Nil)
+            .map(|(name, _)| name)
+    }
+}
+
+/// Instance of a class, with its fields by name.
+pub struct TxInstance<'a> {
+    class: ObjRef,
+    fields: TxMap<'a>,
+}
+
+impl<'a> TxInstance<'a> {
+    /// `class` must reference a `TxClass`.
+    pub fn new(tvm: &'a VM, class: ObjRef) -> Self {
+        debug_assert!(matches!(class.get(), Object::Class(_)));
+        Self {
+            class,
+            fields: TxMap::new(tvm),
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        self.fields.destroy(tvm);
+    }
+
+    pub fn get_class(&self) -> &TxClass<'_> {
+        match self.class.get() {
+            Object::Class(class) => class,
+            _ => unreachable!("instance of a non class object"),
+        }
+    }
+
+    pub fn get_field(&self, name: Value) -> Option<Value> {
+        self.fields.get(name).ok()
+    }
+
+    pub fn set_field(
+        &mut self,
+        tvm: &VM,
+        name: Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        self.fields.set(tvm, name, value)
+    }
+}
+
+/// Method read as a property, called with `receiver` as `self`.
+#[derive(Clone, Copy, Debug)]
+pub struct TxBoundMethod {
+    pub receiver: Value,
+    pub method: Value,
+}
+
+/// GET_PROPERTY: a field of the instance, otherwise a method of its class
+/// bound to it. INVOKE uses `get_field` and `find_method` directly to
+/// avoid allocating the bound method.
+pub fn get_property(
+    tvm: &VM,
+    receiver: Value,
+    name: Value,
+) -> Result<Value, RuntimeError> {
+    let Value::Object(obj) = &receiver else {
+        return Err(RuntimeError::OnlyInstancesHaveProperties);
+    };
+    let Object::Instance(instance) = obj.get() else {
+        return Err(RuntimeError::OnlyInstancesHaveProperties);
+    };
+    if let Some(value) = instance.get_field(name) {
+        return Ok(value);
+    }
+    bind_method(tvm, instance.get_class(), receiver, name)
+}
+
+/// SET_PROPERTY, fields are created on first assignment.
+pub fn set_property(
+    tvm: &VM,
+    receiver: Value,
+    name: Value,
+    value: Value,
+) -> Result<(), RuntimeError> {
+    let Value::Object(obj) = &receiver else {
+        return Err(RuntimeError::OnlyInstancesHaveProperties);
+    };
+    // No other reference into the instance is held while setting a field
+    let Object::Instance(instance) = (unsafe { obj.get_mut() }) else {
+        return Err(RuntimeError::OnlyInstancesHaveProperties);
+    };
+    instance.set_field(tvm, name, value)
+}
+
+/// Bind the method `name` of `class` to `receiver`, an error if the class
+/// has no such method.
+pub fn bind_method(
+    tvm: &VM,
+    class: &TxClass,
+    receiver: Value,
+    name: Value,
+) -> Result<Value, RuntimeError> {
+    let method = class
+        .find_method(name)
+        .ok_or(RuntimeError::UndefinedProperty)?;
+    new_object(tvm, Object::BoundMethod(TxBoundMethod { receiver, method }))
+}
+
+/// INHERIT, copies the methods of `superclass` down to `class` so that
+/// inherited methods are found with a single lookup. It runs before the
+/// METHOD instructions of `class`, which override the copied methods.
+pub fn inherit(
+    tvm: &VM,
+    class: Value,
+    superclass: Value,
+) -> Result<(), RuntimeError> {
+    let Value::Object(superclass_obj) = &superclass else {
+        return Err(RuntimeError::SuperclassMustBeAClass);
+    };
+    let Object::Class(parent) = superclass_obj.get() else {
+        return Err(RuntimeError::SuperclassMustBeAClass);
+    };
+    let Value::Object(class_obj) = &class else {
+        unreachable!("INHERIT on a non class object")
+    };
+    if class_obj == superclass_obj {
+        return Err(RuntimeError::ClassInheritsFromItself);
+    }
+    // The superclass is a different object, it is not borrowed mutably
+    let Object::Class(class) = (unsafe { class_obj.get_mut() }) else {
+        unreachable!("INHERIT on a non class object")
+    };
+    for (name, method) in parent.methods.iter() {
+        class.add_method(tvm, name, method)?;
+    }
+    class.superclass = Some(*superclass_obj);
+    Ok(())
+}
+
+/// GET_SUPER, binds the method `name` of `superclass` to `receiver`,
+/// skipping the overrides in the class of `receiver`. SUPER_INVOKE uses
+/// `find_method` directly.
+pub fn get_super(
+    tvm: &VM,
+    receiver: Value,
+    superclass: Value,
+    name: Value,
+) -> Result<Value, RuntimeError> {
+    let Value::Object(obj) = &superclass else {
+        return Err(RuntimeError::SuperclassMustBeAClass);
+    };
+    let Object::Class(class) = obj.get() else {
+        return Err(RuntimeError::SuperclassMustBeAClass);
+    };
+    bind_method(tvm, class, receiver, name)
+}
+
+/// IS, whether `value` is an instance of `class` or of one of its
+/// subclasses.
+pub fn is_instance_of(
+    value: Value,
+    class: Value,
+) -> Result<bool, RuntimeError> {
+    let Value::Object(class_obj) = &class else {
+        return Err(RuntimeError::IsOperandMustBeAClass);
+    };
+    let Object::Class(class) = class_obj.get() else {
+        return Err(RuntimeError::IsOperandMustBeAClass);
+    };
+    let Value::Object(obj) = &value else {
+        return Ok(false);
+    };
+    Ok(match obj.get() {
+        Object::Instance(instance) => {
+            instance.get_class().is_subclass_of(class)
+        }
+        _ => false,
+    })
+}
+
+/// IMPLEMENT, runs after the METHOD instructions of `class`. Fails if a
+/// required method of `trait_` is missing, otherwise copies down the
+/// default methods `class` does not define and records the trait.
+pub fn implement(
+    tvm: &VM,
+    class: Value,
+    trait_: Value,
+) -> Result<(), RuntimeError> {
+    let Value::Object(trait_obj) = &trait_ else {
+        return Err(RuntimeError::CanOnlyImplementTraits);
+    };
+    let Object::Trait(trait_ref) = trait_obj.get() else {
+        return Err(RuntimeError::CanOnlyImplementTraits);
+    };
+    let Value::Object(class_obj) = &class else {
+        unreachable!("IMPLEMENT on a non class object")
+    };
+    // The trait is a different object, it is not borrowed mutably
+    let Object::Class(class) = (unsafe { class_obj.get_mut() }) else {
+        unreachable!("IMPLEMENT on a non class object")
+    };
+    // Checked first so that a failure leaves the class unchanged
+    if trait_ref
+        .required_methods()
+        .any(|name| class.find_method(name).is_none())
+    {
+        return Err(RuntimeError::MissingTraitMethod);
+    }
+    for (name, method) in trait_ref.methods.iter() {
+        if method != Value::Nil && class.find_method(name).is_none() {
+            class.add_method(tvm, name, method)?;
+        }
+    }
+    if !class.traits.contains(trait_) {
+        class.traits.push(tvm, trait_)?;
+    }
+    Ok(())
+}
+
+/// IMPLEMENTS, whether `value` is an instance of a class that implements
+/// `trait_`, directly or through a superclass.
+pub fn implements(value: Value, trait_: Value) -> Result<bool, RuntimeError> {
+    let is_trait = match &trait_ {
+        Value::Object(obj) => matches!(obj.get(), Object::Trait(_)),
+        _ => false,
+    };
+    if !is_trait {
+        return Err(RuntimeError::ImplementsOperandMustBeATrait);
+    }
+    let Value::Object(obj) = &value else {
+        return Ok(false);
+    };
+    let Object::Instance(instance) = obj.get() else {
+        return Ok(false);
+    };
+    let mut current = Some(instance.get_class());
+    while let Some(class) = current {
+        if class.traits.contains(trait_) {
+            return Ok(true);
+        }
+        current = class.get_superclass();
+    }
+    Ok(false)
+}
+
+fn is_string(value: Value, string: &str) -> bool {
+    match &value {
+        Value::Object(obj) => {
+            matches!(obj.get(), Object::String(other) if other.as_str() == string)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocator::AllocCategory, object::free_object, string::StringInterner,
+    };
+
+    #[test]
+    fn test_properties() {
+        let tvm = VM::new();
+        let mut strings = StringInterner::new(&tvm);
+        let mut name = |string| strings.intern(&tvm, string).unwrap();
+        let (point, init, norm, x) =
+            (name("Point"), name("init"), name("norm"), name("x"));
+        let mut class = TxClass::new(&tvm, point);
+        // Closures are stood in for by integers
+        assert_eq!(class.add_method(&tvm, init, Value::Int(1)), Ok(()));
+        assert_eq!(class.add_method(&tvm, norm, Value::Int(2)), Ok(()));
+        assert_eq!(class.get_initializer(), Some(Value::Int(1)));
+        assert_eq!(class.find_method(x), None);
+        let Value::Object(class) =
+            new_object(&tvm, Object::Class(class)).unwrap()
+        else {
+            unreachable!()
+        };
+        let instance =
+            new_object(&tvm, Object::Instance(TxInstance::new(&tvm, class)))
+                .unwrap();
+        assert_eq!(
+            get_property(&tvm, instance, x),
+            Err(RuntimeError::UndefinedProperty)
+        );
+        assert_eq!(set_property(&tvm, instance, x, Value::Int(3)), Ok(()));
+        assert_eq!(get_property(&tvm, instance, x), Ok(Value::Int(3)));
+        // Fields shadow methods
+        assert_eq!(set_property(&tvm, instance, init, Value::Nil), Ok(()));
+        assert_eq!(get_property(&tvm, instance, init), Ok(Value::Nil));
+        let Ok(Value::Object(bound)) = get_property(&tvm, instance, norm)
+        else {
+            panic!("method not bound")
+        };
+        let Object::BoundMethod(method) = bound.get() else {
+            panic!("method not bound")
+        };
+        assert_eq!(method.receiver, instance);
+        assert_eq!(method.method, Value::Int(2));
+        assert_eq!(
+            set_property(&tvm, Value::Int(1), x, Value::Nil),
+            Err(RuntimeError::OnlyInstancesHaveProperties)
+        );
+        assert_eq!(
+            get_property(&tvm, point, x),
+            Err(RuntimeError::OnlyInstancesHaveProperties)
+        );
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::Class as usize].count, 3);
+        unsafe {
+            free_object(&tvm, bound);
+            let Value::Object(instance) = instance else {
+                unreachable!()
+            };
+            free_object(&tvm, instance);
+            free_object(&tvm, class);
+            strings.destroy(&tvm);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let tvm = VM::new();
+        let mut strings = StringInterner::new(&tvm);
+        let mut name = |string| strings.intern(&tvm, string).unwrap();
+        let (a, b, init, speak, walk) = (
+            name("A"),
+            name("B"),
+            name("init"),
+            name("speak"),
+            name("walk"),
+        );
+        let mut new_class = |name, methods: &[(Value, Value)]| {
+            let mut class = TxClass::new(&tvm, name);
+            for &(name, method) in methods {
+                class.add_method(&tvm, name, method).unwrap();
+            }
+            new_object(&tvm, Object::Class(class)).unwrap()
+        };
+        let parent =
+            new_class(a, &[(init, Value::Int(1)), (speak, Value::Int(2))]);
+        let child = new_class(b, &[]);
+        assert_eq!(
+            inherit(&tvm, child, Value::Int(1)),
+            Err(RuntimeError::SuperclassMustBeAClass)
+        );
+        assert_eq!(
+            inherit(&tvm, child, child),
+            Err(RuntimeError::ClassInheritsFromItself)
+        );
+        assert_eq!(inherit(&tvm, child, parent), Ok(()));
+        let Value::Object(child_obj) = child else {
+            unreachable!()
+        };
+        let Object::Class(child_class) = (unsafe { child_obj.get_mut() })
+        else {
+            unreachable!()
+        };
+        // Overrides come after INHERIT
+        assert_eq!(child_class.add_method(&tvm, speak, Value::Int(3)), Ok(()));
+        assert_eq!(child_class.add_method(&tvm, walk, Value::Int(4)), Ok(()));
+        assert_eq!(child_class.get_initializer(), Some(Value::Int(1)));
+        assert_eq!(child_class.find_method(speak), Some(Value::Int(3)));
+        let instance = new_object(
+            &tvm,
+            Object::Instance(TxInstance::new(&tvm, child_obj)),
+        )
+        .unwrap();
+        assert_eq!(is_instance_of(instance, child), Ok(true));
+        assert_eq!(is_instance_of(instance, parent), Ok(true));
+        assert_eq!(is_instance_of(Value::Int(1), parent), Ok(false));
+        assert_eq!(is_instance_of(parent, parent), Ok(false));
+        assert_eq!(
+            is_instance_of(instance, instance),
+            Err(RuntimeError::IsOperandMustBeAClass)
+        );
+        let Ok(Value::Object(bound)) =
+            get_super(&tvm, instance, parent, speak)
+        else {
+            panic!("super method not bound")
+        };
+        let Object::BoundMethod(method) = bound.get() else {
+            panic!("super method not bound")
+        };
+        assert_eq!(method.receiver, instance);
+        assert_eq!(method.method, Value::Int(2));
+        assert_eq!(
+            get_super(&tvm, instance, parent, walk),
+            Err(RuntimeError::UndefinedProperty)
+        );
+        unsafe {
+            for value in [Value::Object(bound), instance, child, parent] {
+                let Value::Object(obj) = value else {
+                    unreachable!()
+                };
+                free_object(&tvm, obj);
+            }
+            strings.destroy(&tvm);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_traits() {
+        let tvm = VM::new();
+        let mut strings = StringInterner::new(&tvm);
+        let mut name = |string| strings.intern(&tvm, string).unwrap();
+        let (a, b, named, describe, get_name) = (
+            name("A"),
+            name("B"),
+            name("Named"),
+            name("describe"),
+            name("get_name"),
+        );
+        let mut trait_ = TxTrait::new(&tvm, named);
+        assert_eq!(trait_.add_method(&tvm, get_name, Value::Nil), Ok(()));
+        assert_eq!(trait_.add_method(&tvm, describe, Value::Int(1)), Ok(()));
+        assert_eq!(trait_.required_methods().collect::<Vec<_>>(), [get_name]);
+        let trait_ = new_object(&tvm, Object::Trait(trait_)).unwrap();
+        let mut new_class = |name, methods: &[(Value, Value)]| {
+            let mut class = TxClass::new(&tvm, name);
+            for &(name, method) in methods {
+                class.add_method(&tvm, name, method).unwrap();
+            }
+            new_object(&tvm, Object::Class(class)).unwrap()
+        };
+        let parent = new_class(a, &[]);
+        assert_eq!(
+            implement(&tvm, parent, trait_),
+            Err(RuntimeError::MissingTraitMethod)
+        );
+        assert_eq!(
+            implement(&tvm, parent, parent),
+            Err(RuntimeError::CanOnlyImplementTraits)
+        );
+        let child = new_class(b, &[(get_name, Value::Int(2))]);
+        assert_eq!(inherit(&tvm, child, parent), Ok(()));
+        assert_eq!(implement(&tvm, child, trait_), Ok(()));
+        let Value::Object(child_obj) = child else {
+            unreachable!()
+        };
+        let Object::Class(child_class) = child_obj.get() else {
+            unreachable!()
+        };
+        assert_eq!(child_class.find_method(describe), Some(Value::Int(1)));
+        assert_eq!(child_class.find_method(get_name), Some(Value::Int(2)));
+        let mut new_instance = |class| {
+            let Value::Object(class) = class else {
+                unreachable!()
+            };
+            new_object(&tvm, Object::Instance(TxInstance::new(&tvm, class)))
+                .unwrap()
+        };
+        let (child_instance, parent_instance) =
+            (new_instance(child), new_instance(parent));
+        assert_eq!(implements(child_instance, trait_), Ok(true));
+        assert_eq!(implements(parent_instance, trait_), Ok(false));
+        assert_eq!(implements(Value::Nil, trait_), Ok(false));
+        assert_eq!(
+            implements(child_instance, child),
+            Err(RuntimeError::ImplementsOperandMustBeATrait)
+        );
+        unsafe {
+            for value in
+                [child_instance, parent_instance, child, parent, trait_]
+            {
+                let Value::Object(obj) = value else {
+                    unreachable!()
+                };
+                free_object(&tvm, obj);
+            }
+            strings.destroy(&tvm);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+}
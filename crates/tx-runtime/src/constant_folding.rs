@@ -0,0 +1,196 @@
+use std::fmt;
+
+use crate::{
+    arithmetic::{self, ArithmeticOp, BitwiseOp, ComparisonOp},
+    opcodes::*,
+    value::Value,
+    vm::RuntimeError,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FoldError {
+    IntegerOverflow,
+    DivisionByZero,
+    NegativeShiftCount,
+}
+
+impl FoldError {
+    // Type errors are left to the runtime, arithmetic errors are reported
+    // at compile time.
+    fn from_runtime(err: RuntimeError) -> Result<Option<Value>, FoldError> {
+        match err {
+            RuntimeError::IntegerOverflow => Err(Self::IntegerOverflow),
+            RuntimeError::DivisionByZero => Err(Self::DivisionByZero),
+            RuntimeError::NegativeShiftCount => Err(Self::NegativeShiftCount),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl fmt::Display for FoldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IntegerOverflow => {
+                write!(f, "Integer overflow in constant expression.")
+            }
+            Self::DivisionByZero => {
+                write!(f, "Division by zero in constant expression.")
+            }
+            Self::NegativeShiftCount => {
+                write!(f, "Negative shift count in constant expression.")
+            }
+        }
+    }
+}
+
+/// Evaluate a unary operator on a literal operand at compile time.
+///
+/// Returns `Ok(None)` when the operation can not be folded and has to be
+/// left to the runtime (that will report type errors).
+pub fn fold_unary(
+    opc: OpCode,
+    operand: Value,
+) -> Result<Option<Value>, FoldError> {
+    match opc {
+        NOT => Ok(Some(Value::Bool(operand.is_falsey()))),
+        NEGATE => arithmetic::negate(operand)
+            .map_or_else(FoldError::from_runtime, |value| Ok(Some(value))),
+        BIT_NOT => arithmetic::bit_not(operand)
+            .map_or_else(FoldError::from_runtime, |value| Ok(Some(value))),
+        _ => Ok(None),
+    }
+}
+
+/// Evaluate a binary operator on literal operands at compile time.
+///
+/// Returns `Ok(None)` when the operation can not be folded and has to be
+/// left to the runtime (that will report type errors).
+pub fn fold_binary(
+    opc: OpCode,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Option<Value>, FoldError> {
+    let result = if let Some(op) = ArithmeticOp::from_opcode(opc) {
+        arithmetic::binary_op(op, lhs, rhs)
+    } else if let Some(op) = ComparisonOp::from_opcode(opc) {
+        arithmetic::comparison_op(op, lhs, rhs)
+    } else if let Some(op) = BitwiseOp::from_opcode(opc) {
+        arithmetic::bitwise_op(op, lhs, rhs)
+    } else {
+        return Ok(None);
+    };
+    result.map_or_else(FoldError::from_runtime, |value| Ok(Some(value)))
+}
+
+/// Fold `lhs and rhs` for literal operands.
+pub fn fold_and(lhs: Value, rhs: Value) -> Value {
+    if lhs.is_falsey() {
+        lhs
+    } else {
+        rhs
+    }
+}
+
+/// Fold `lhs or rhs` for literal operands.
+pub fn fold_or(lhs: Value, rhs: Value) -> Value {
+    if lhs.is_falsey() {
+        rhs
+    } else {
+        lhs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{TxFloat, TxInt};
+
+    #[test]
+    fn test_fold_int() {
+        let fold = |opc, a, b| fold_binary(opc, Value::Int(a), Value::Int(b));
+        assert!(matches!(fold(ADD, 1, 2), Ok(Some(Value::Int(3)))));
+        assert!(matches!(fold(DIVIDE, 7, 2), Ok(Some(Value::Int(3)))));
+        assert!(matches!(fold(LESS, 1, 2), Ok(Some(Value::Bool(true)))));
+        assert_eq!(fold(ADD, TxInt::MAX, 1), Err(FoldError::IntegerOverflow));
+        assert_eq!(
+            fold(MULTIPLY, TxInt::MIN, -1),
+            Err(FoldError::IntegerOverflow)
+        );
+        assert_eq!(
+            fold(DIVIDE, TxInt::MIN, -1),
+            Err(FoldError::IntegerOverflow)
+        );
+        assert_eq!(fold(DIVIDE, 1, 0), Err(FoldError::DivisionByZero));
+        assert_eq!(fold(MODULO, 1, 0), Err(FoldError::DivisionByZero));
+        assert!(matches!(fold(POWER, 2, 10), Ok(Some(Value::Int(1024)))));
+        assert!(matches!(fold(SHIFT_LEFT, 1, 3), Ok(Some(Value::Int(8)))));
+        assert_eq!(
+            fold(SHIFT_LEFT, 1, -3),
+            Err(FoldError::NegativeShiftCount)
+        );
+        assert_eq!(
+            fold_unary(NEGATE, Value::Int(TxInt::MIN)),
+            Err(FoldError::IntegerOverflow)
+        );
+    }
+
+    #[test]
+    fn test_fold_float() {
+        let fold =
+            |opc, a, b| fold_binary(opc, Value::Float(a), Value::Float(b));
+        assert_eq!(
+            fold(DIVIDE, 1.0, 0.0),
+            Ok(Some(Value::Float(TxFloat::INFINITY)))
+        );
+        let nan = TxFloat::NAN;
+        assert!(matches!(
+            fold(EQUAL, nan, nan),
+            Ok(Some(Value::Bool(false)))
+        ));
+        assert!(matches!(
+            fold(NOT_EQUAL, nan, nan),
+            Ok(Some(Value::Bool(true)))
+        ));
+        assert!(matches!(
+            fold(GREATER_EQUAL, 2.0, 2.0),
+            Ok(Some(Value::Bool(true)))
+        ));
+    }
+
+    #[test]
+    fn test_fold_other() {
+        assert!(matches!(
+            fold_binary(LESS, Value::Char('a'), Value::Char('b')),
+            Ok(Some(Value::Bool(true)))
+        ));
+        assert!(matches!(
+            fold_binary(EQUAL, Value::Bool(true), Value::Bool(false)),
+            Ok(Some(Value::Bool(false)))
+        ));
+        assert!(matches!(
+            fold_binary(ADD, Value::Bool(true), Value::Bool(false)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            fold_binary(ADD, Value::Int(1), Value::Float(1.0)),
+            Ok(Some(Value::Float(2.0)))
+        ));
+        assert!(matches!(
+            fold_binary(EQUAL, Value::Int(1), Value::Nil),
+            Ok(Some(Value::Bool(false)))
+        ));
+        assert!(matches!(
+            fold_binary(LESS, Value::Bool(true), Value::Bool(false)),
+            Ok(None)
+        ));
+        assert!(matches!(
+            fold_unary(NOT, Value::Nil),
+            Ok(Some(Value::Bool(true)))
+        ));
+        assert!(matches!(
+            fold_and(Value::Bool(false), Value::Int(1)),
+            Value::Bool(false)
+        ));
+        assert!(matches!(fold_or(Value::Nil, Value::Int(1)), Value::Int(1)));
+    }
+}
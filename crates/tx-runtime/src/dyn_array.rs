@@ -7,6 +7,23 @@ use std::{
     ptr::{self, Unique},
 };
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TryReserveError {
+    CapacityOverflow,
+    AllocError { layout: Layout },
+}
+
+// Infallible allocation failures are fatal, like for std collections
+pub(crate) fn handle_reserve<T>(result: Result<T, TryReserveError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(TryReserveError::CapacityOverflow) => panic!("capacity overflow"),
+        Err(TryReserveError::AllocError { layout }) => {
+            handle_alloc_error(layout)
+        }
+    }
+}
+
 pub struct RawDynArray<'a, T, A: Allocator> {
     ptr: Unique<T>,
     cap: usize,
@@ -28,7 +45,7 @@ impl<'a, T, A: Allocator> RawDynArray<'a, T, A> {
         1
     };
 
-    fn new(alloc: & 'a A) -> Self {
+    fn new(alloc: &'a A) -> Self {
         Self {
             ptr: Unique::dangling(),
             cap: if mem::size_of::<T>() == 0 {
@@ -48,6 +65,16 @@ impl<'a, T, A: Allocator> RawDynArray<'a, T, A> {
         len: usize,
         additional: usize,
     ) {
+        handle_reserve(self.try_reserve(alloc, len, additional));
+    }
+
+    #[inline]
+    pub unsafe fn try_reserve(
+        &mut self,
+        alloc: &A,
+        len: usize,
+        additional: usize,
+    ) -> Result<(), TryReserveError> {
         debug_assert!(ptr::eq(alloc, self.allocator));
         // Callers expect this function to be very cheap when there is already
         // sufficient capacity. Therefore, we move all the resizing and
@@ -60,33 +87,41 @@ impl<'a, T, A: Allocator> RawDynArray<'a, T, A> {
             alloc: &A,
             len: usize,
             additional: usize,
-        ) {
-            unsafe { slf.grow(alloc, len, additional); }
+        ) -> Result<(), TryReserveError> {
+            unsafe { slf.grow(alloc, len, additional) }
         }
 
         if additional > self.cap.wrapping_sub(len) {
-            do_reserve_and_handle(self, alloc, len, additional);
+            do_reserve_and_handle(self, alloc, len, additional)
+        } else {
+            Ok(())
         }
     }
 
-    unsafe fn grow(&mut self, alloc: &A, len: usize, additional: usize) {
+    unsafe fn grow(
+        &mut self,
+        alloc: &A,
+        len: usize,
+        additional: usize,
+    ) -> Result<(), TryReserveError> {
         debug_assert!(ptr::eq(alloc, self.allocator));
         // since we set the capacity to usize::MAX when T has size 0,
         // getting to here necessarily means the Vec is overfull.
-        assert!(mem::size_of::<T>() != 0, "capacity overflow");
-        let required_cap = len + additional;
+        if mem::size_of::<T>() == 0 {
+            return Err(TryReserveError::CapacityOverflow);
+        }
+        let required_cap = len
+            .checked_add(additional)
+            .ok_or(TryReserveError::CapacityOverflow)?;
         // This can't overflow because we ensure self.cap <= isize::MAX.
         let new_cap = cmp::max(2 * self.cap, required_cap);
         let new_cap = cmp::max(Self::MIN_NON_ZERO_CAP, new_cap);
-        // `Layout::array` checks that the number of bytes is <= usize::MAX,
-        // but this is redundant since old_layout.size() <= isize::MAX,
-        // so the `unwrap` should never fail.
-        let new_layout = Layout::array::<T>(new_cap).unwrap();
+        let new_layout = Layout::array::<T>(new_cap)
+            .map_err(|_| TryReserveError::CapacityOverflow)?;
         // Ensure that the new allocation doesn't exceed `isize::MAX` bytes.
-        assert!(
-            new_layout.size() <= isize::MAX as usize,
-            "Allocation too large"
-        );
+        if new_layout.size() > isize::MAX as usize {
+            return Err(TryReserveError::CapacityOverflow);
+        }
         let new_ptr = if self.cap == 0 {
             alloc.allocate(new_layout)
         } else {
@@ -97,9 +132,41 @@ impl<'a, T, A: Allocator> RawDynArray<'a, T, A> {
         };
         self.ptr = match new_ptr {
             Ok(p) => unsafe { Unique::new_unchecked(p.cast().as_ptr()) },
-            Err(_) => handle_alloc_error(new_layout),
+            Err(_) => {
+                return Err(TryReserveError::AllocError { layout: new_layout })
+            }
         };
         self.cap = new_cap;
+        Ok(())
+    }
+
+    unsafe fn shrink(
+        &mut self,
+        alloc: &A,
+        len: usize,
+    ) -> Result<(), TryReserveError> {
+        #[cfg(debug_assertions)]
+        debug_assert!(ptr::eq(alloc, self.allocator));
+        if mem::size_of::<T>() == 0 || len >= self.cap {
+            return Ok(());
+        }
+        if len == 0 {
+            self.destroy(alloc);
+            return Ok(());
+        }
+        let old_layout = Layout::array::<T>(self.cap).unwrap();
+        let new_layout = Layout::array::<T>(len).unwrap();
+        let new_ptr = unsafe {
+            alloc.shrink(self.ptr.cast().into(), old_layout, new_layout)
+        };
+        self.ptr = match new_ptr {
+            Ok(p) => unsafe { Unique::new_unchecked(p.cast().as_ptr()) },
+            Err(_) => {
+                return Err(TryReserveError::AllocError { layout: new_layout })
+            }
+        };
+        self.cap = len;
+        Ok(())
     }
 
     unsafe fn destroy(&mut self, alloc: &A) {
@@ -110,6 +177,8 @@ impl<'a, T, A: Allocator> RawDynArray<'a, T, A> {
             unsafe {
                 alloc.deallocate(self.ptr.cast().into(), layout);
             }
+            self.ptr = Unique::dangling();
+            self.cap = 0;
         }
     }
 }
@@ -137,7 +206,7 @@ impl<'a, T, A: Allocator> DynArray<'a, T, A> {
         self.buf.cap
     }
 
-    pub fn new(alloc: & 'a A) -> Self {
+    pub fn new(alloc: &'a A) -> Self {
         DynArray {
             buf: RawDynArray::new(alloc),
             len: 0,
@@ -153,12 +222,39 @@ impl<'a, T, A: Allocator> DynArray<'a, T, A> {
         self.buf.reserve(alloc, self.len, additional)
     }
 
+    pub unsafe fn try_reserve(
+        &mut self,
+        alloc: &A,
+        additional: usize,
+    ) -> Result<(), TryReserveError> {
+        self.buf.try_reserve(alloc, self.len, additional)
+    }
+
+    /// Release the unused capacity. On error the array is left unchanged.
+    pub unsafe fn try_shrink_to_fit(
+        &mut self,
+        alloc: &A,
+    ) -> Result<(), TryReserveError> {
+        self.buf.shrink(alloc, self.len)
+    }
+
     pub unsafe fn push(&mut self, alloc: &A, elem: T) {
-        self.buf.reserve(alloc, self.len, 1);
+        handle_reserve(self.try_push(alloc, elem));
+    }
+
+    /// Like `push` but returns an error instead of aborting if the memory
+    /// can not be allocated, `elem` is then dropped.
+    pub unsafe fn try_push(
+        &mut self,
+        alloc: &A,
+        elem: T,
+    ) -> Result<(), TryReserveError> {
+        self.buf.try_reserve(alloc, self.len, 1)?;
         unsafe {
             ptr::write(self.ptr().add(self.len), elem);
         }
         self.len += 1;
+        Ok(())
     }
 
     pub fn pop(&mut self) -> Option<T> {
@@ -171,8 +267,19 @@ impl<'a, T, A: Allocator> DynArray<'a, T, A> {
     }
 
     pub unsafe fn insert(&mut self, alloc: &A, index: usize, elem: T) {
+        handle_reserve(self.try_insert(alloc, index, elem));
+    }
+
+    /// Like `insert` but returns an error instead of aborting if the memory
+    /// can not be allocated, `elem` is then dropped.
+    pub unsafe fn try_insert(
+        &mut self,
+        alloc: &A,
+        index: usize,
+        elem: T,
+    ) -> Result<(), TryReserveError> {
         assert!(index <= self.len, "index out of bounds");
-        self.buf.reserve(alloc, self.len, 1);
+        self.buf.try_reserve(alloc, self.len, 1)?;
         unsafe {
             ptr::copy(
                 self.ptr().add(index),
@@ -182,6 +289,7 @@ impl<'a, T, A: Allocator> DynArray<'a, T, A> {
             ptr::write(self.ptr().add(index), elem);
             self.len += 1;
         }
+        Ok(())
     }
 
     pub fn remove(&mut self, index: usize) -> T {
@@ -200,6 +308,25 @@ impl<'a, T, A: Allocator> DynArray<'a, T, A> {
 }
 
 impl<'a, T: Clone, A: Allocator> DynArray<'a, T, A> {
+    pub unsafe fn extend_from_slice(&mut self, alloc: &A, other: &[T]) {
+        handle_reserve(self.try_extend_from_slice(alloc, other));
+    }
+
+    /// Like `extend_from_slice` but returns an error instead of aborting if
+    /// the memory can not be allocated, the array is then left unchanged.
+    pub unsafe fn try_extend_from_slice(
+        &mut self,
+        alloc: &A,
+        other: &[T],
+    ) -> Result<(), TryReserveError> {
+        self.try_reserve(alloc, other.len())?;
+        for elem in other {
+            // Can not fail after the reserve
+            self.try_push(alloc, elem.clone())?;
+        }
+        Ok(())
+    }
+
     pub unsafe fn resize(&mut self, alloc: &A, new_len: usize, value: T) {
         if self.len < new_len {
             self.reserve(alloc, new_len.wrapping_sub(self.len));
@@ -367,3 +494,28 @@ impl<'a, T> Drop for Drain<'a, T> {
         for _ in &mut *self {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_try_push_over_limit() {
+        let tvm = VM::new();
+        tvm.allocator.set_limit_bytes(Some(64));
+        let mut array = DynArray::<u64, _>::new(&tvm.allocator);
+        unsafe {
+            for i in 0..8 {
+                assert_eq!(array.try_push(&tvm.allocator, i), Ok(()));
+            }
+            assert!(matches!(
+                array.try_push(&tvm.allocator, 8),
+                Err(TryReserveError::AllocError { .. })
+            ));
+            assert_eq!(array.len(), 8);
+            assert_eq!(tvm.allocator.allocated_bytes(), 64);
+            array.destroy(&tvm.allocator);
+        }
+    }
+}
@@ -1,7 +1,15 @@
-use std::{alloc::Allocator, ptr::Unique};
+use std::{
+    alloc::{Allocator, Layout},
+    mem,
+    ptr::{self, Unique},
+};
+
+use crate::dyn_array::{handle_reserve, TryReserveError};
 
 pub trait HashMapKey<T> {
     const EMPTY_KEY: T;
+
+    fn get_hash(&self) -> usize;
 }
 
 pub trait HashMapValue<T> {
@@ -9,6 +17,17 @@ pub trait HashMapValue<T> {
     const TOMBSTONE_VALUE: T;
 }
 
+impl HashMapValue<usize> for usize {
+    const EMPTY_VALUE: usize = 0;
+    const TOMBSTONE_VALUE: usize = 1;
+}
+
+// For sets, the value is only used to tell tombstones from empty entries
+impl HashMapValue<bool> for bool {
+    const EMPTY_VALUE: bool = false;
+    const TOMBSTONE_VALUE: bool = true;
+}
+
 pub struct HashMap<'a, KeyT, ValueT, A: Allocator>
 where
     KeyT: HashMapKey<KeyT>,
@@ -26,10 +45,333 @@ pub struct Entry<KeyT, ValueT> {
     value: ValueT,
 }
 
+impl<KeyT, ValueT> Entry<KeyT, ValueT>
+where
+    KeyT: HashMapKey<KeyT> + PartialEq,
+    ValueT: HashMapValue<ValueT> + PartialEq,
+{
+    fn is_empty(&self) -> bool {
+        self.key == KeyT::EMPTY_KEY && self.value == ValueT::EMPTY_VALUE
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.key == KeyT::EMPTY_KEY && self.value == ValueT::TOMBSTONE_VALUE
+    }
+}
+
 impl<KeyT, ValueT, A: Allocator> HashMap<'_, KeyT, ValueT, A>
 where
     KeyT: HashMapKey<KeyT>,
     ValueT: HashMapValue<ValueT>,
 {
     pub(crate) const MAX_LOAD_FACTOR: f32 = 0.75;
+    const MIN_CAP: usize = 8;
+}
+
+impl<'a, KeyT, ValueT, A: Allocator> HashMap<'a, KeyT, ValueT, A>
+where
+    KeyT: HashMapKey<KeyT> + PartialEq,
+    ValueT: HashMapValue<ValueT> + PartialEq,
+{
+    pub fn new(alloc: &'a A) -> Self {
+        Self {
+            ptr: Unique::dangling(),
+            cap: 0,
+            len: 0,
+            #[cfg(debug_assertions)]
+            allocator: alloc,
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, alloc: &A) {
+        #[cfg(debug_assertions)]
+        debug_assert!(ptr::eq(alloc, self.allocator));
+        if self.cap != 0 {
+            for entry in self.entries_mut() {
+                ptr::drop_in_place(entry);
+            }
+            let layout =
+                Layout::array::<Entry<KeyT, ValueT>>(self.cap).unwrap();
+            alloc.deallocate(self.ptr.cast().into(), layout);
+            self.ptr = Unique::dangling();
+            self.cap = 0;
+            self.len = 0;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries()
+            .iter()
+            .all(|entry| entry.key == KeyT::EMPTY_KEY)
+    }
+
+    pub fn get(&self, key: &KeyT) -> Option<&ValueT> {
+        if self.len == 0 {
+            return None;
+        }
+        let entry = &self.entries()[self.find_entry(key)];
+        if entry.key == KeyT::EMPTY_KEY {
+            None
+        } else {
+            Some(&entry.value)
+        }
+    }
+
+    /// Find a stored key from its hash without building the key, e.g. an
+    /// interned string from its content.
+    pub fn find_key(
+        &self,
+        hash: usize,
+        is_match: impl Fn(&KeyT) -> bool,
+    ) -> Option<&KeyT> {
+        if self.len == 0 {
+            return None;
+        }
+        let entries = self.entries();
+        let mask = self.cap - 1;
+        let mut idx = hash & mask;
+        loop {
+            let entry = &entries[idx];
+            if entry.is_empty() {
+                return None;
+            }
+            if entry.key != KeyT::EMPTY_KEY && is_match(&entry.key) {
+                return Some(&entry.key);
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    /// Insert a key or update its value, returns true if the key was not
+    /// present.
+    pub unsafe fn set(&mut self, alloc: &A, key: KeyT, value: ValueT) -> bool {
+        handle_reserve(self.try_set(alloc, key, value))
+    }
+
+    /// Like `set` but returns an error instead of aborting if the memory
+    /// can not be allocated, the map is then left unchanged.
+    pub unsafe fn try_set(
+        &mut self,
+        alloc: &A,
+        key: KeyT,
+        value: ValueT,
+    ) -> Result<bool, TryReserveError> {
+        debug_assert!(key != KeyT::EMPTY_KEY);
+        // Updating an existing key never allocates. The stored key is kept,
+        // it may differ from an equal `key`.
+        if self.cap != 0 {
+            let idx = self.find_entry(&key);
+            if self.entries()[idx].key == key {
+                self.entries_mut()[idx].value = value;
+                return Ok(false);
+            }
+        }
+        if (self.len + 1) as f32 > self.cap as f32 * Self::MAX_LOAD_FACTOR {
+            self.grow(alloc)?;
+        }
+        let idx = self.find_entry(&key);
+        // Reusing a tombstone does not change len as it was already counted
+        if self.entries()[idx].is_empty() {
+            self.len += 1;
+        }
+        self.entries_mut()[idx] = Entry { key, value };
+        Ok(true)
+    }
+
+    /// Remove a key, returns true if the key was present.
+    pub fn remove(&mut self, key: &KeyT) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        let idx = self.find_entry(key);
+        let entry = &mut self.entries_mut()[idx];
+        if entry.key == KeyT::EMPTY_KEY {
+            return false;
+        }
+        *entry = Entry {
+            key: KeyT::EMPTY_KEY,
+            value: ValueT::TOMBSTONE_VALUE,
+        };
+        true
+    }
+
+    /// Iterate over the entries in storage order.
+    pub fn iter(&self) -> impl Iterator<Item = (&KeyT, &ValueT)> {
+        self.entries()
+            .iter()
+            .filter(|entry| entry.key != KeyT::EMPTY_KEY)
+            .map(|entry| (&entry.key, &entry.value))
+    }
+
+    fn entries(&self) -> &[Entry<KeyT, ValueT>] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.cap) }
+    }
+
+    fn entries_mut(&mut self) -> &mut [Entry<KeyT, ValueT>] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.cap) }
+    }
+
+    // Capacity is always a power of 2 and the load factor guarantees at
+    // least one empty entry, so the probing always terminates.
+    fn find_entry(&self, key: &KeyT) -> usize {
+        let entries = self.entries();
+        let mask = self.cap - 1;
+        let mut idx = key.get_hash() & mask;
+        let mut tombstone = None;
+        loop {
+            let entry = &entries[idx];
+            if entry.key == *key {
+                return idx;
+            }
+            if entry.is_empty() {
+                return tombstone.unwrap_or(idx);
+            }
+            if entry.is_tombstone() && tombstone.is_none() {
+                tombstone = Some(idx);
+            }
+            idx = (idx + 1) & mask;
+        }
+    }
+
+    /// Release memory after many removals, to the smallest capacity that
+    /// holds the remaining keys. On error the map is left unchanged.
+    pub unsafe fn try_shrink_to_fit(
+        &mut self,
+        alloc: &A,
+    ) -> Result<(), TryReserveError> {
+        let count = self.iter().count();
+        if count == 0 {
+            self.destroy(alloc);
+            return Ok(());
+        }
+        let mut new_cap = Self::MIN_CAP;
+        while count as f32 > new_cap as f32 * Self::MAX_LOAD_FACTOR {
+            new_cap *= 2;
+        }
+        if new_cap < self.cap {
+            self.resize(alloc, new_cap)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn grow(&mut self, alloc: &A) -> Result<(), TryReserveError> {
+        let new_cap = if self.cap == 0 {
+            Self::MIN_CAP
+        } else {
+            self.cap * 2
+        };
+        self.resize(alloc, new_cap)
+    }
+
+    unsafe fn resize(
+        &mut self,
+        alloc: &A,
+        new_cap: usize,
+    ) -> Result<(), TryReserveError> {
+        #[cfg(debug_assertions)]
+        debug_assert!(ptr::eq(alloc, self.allocator));
+        let old_ptr = self.ptr;
+        let old_cap = self.cap;
+        let new_layout = Layout::array::<Entry<KeyT, ValueT>>(new_cap)
+            .map_err(|_| TryReserveError::CapacityOverflow)?;
+        self.ptr = match alloc.allocate(new_layout) {
+            Ok(p) => Unique::new_unchecked(p.cast().as_ptr()),
+            Err(_) => {
+                return Err(TryReserveError::AllocError { layout: new_layout })
+            }
+        };
+        self.cap = new_cap;
+        self.len = 0;
+        for entry in self.entries_mut() {
+            ptr::write(
+                entry,
+                Entry {
+                    key: KeyT::EMPTY_KEY,
+                    value: ValueT::EMPTY_VALUE,
+                },
+            );
+        }
+        if old_cap == 0 {
+            return Ok(());
+        }
+        // Tombstones are not copied over, so len is recomputed
+        for i in 0..old_cap {
+            let entry = ptr::read(old_ptr.as_ptr().add(i));
+            if entry.key == KeyT::EMPTY_KEY {
+                mem::forget(entry);
+                continue;
+            }
+            let idx = self.find_entry(&entry.key);
+            self.entries_mut()[idx] = entry;
+            self.len += 1;
+        }
+        let old_layout =
+            Layout::array::<Entry<KeyT, ValueT>>(old_cap).unwrap();
+        alloc.deallocate(old_ptr.cast().into(), old_layout);
+        Ok(())
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<KeyT, ValueT, A: Allocator> Drop for HashMap<'_, KeyT, ValueT, A>
+where
+    KeyT: HashMapKey<KeyT>,
+    ValueT: HashMapValue<ValueT>,
+{
+    fn drop(&mut self) {
+        debug_assert_eq!(self.cap, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VM;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Key(usize);
+
+    impl HashMapKey<Key> for Key {
+        const EMPTY_KEY: Key = Key(usize::MAX);
+
+        fn get_hash(&self) -> usize {
+            // Poor hash on purpose to exercise collisions
+            self.0 % 3
+        }
+    }
+
+    #[test]
+    fn test_set_get_remove() {
+        let tvm = VM::new();
+        let mut map = HashMap::<Key, usize, _>::new(&tvm.allocator);
+        assert!(map.is_empty());
+        assert_eq!(map.get(&Key(1)), None);
+        unsafe {
+            for i in 0..100 {
+                assert!(map.set(&tvm.allocator, Key(i), i + 10));
+            }
+            assert!(!map.set(&tvm.allocator, Key(5), 42));
+        }
+        assert_eq!(map.get(&Key(5)), Some(&42));
+        assert_eq!(map.get(&Key(99)), Some(&109));
+        assert_eq!(map.get(&Key(100)), None);
+        assert!(map.remove(&Key(5)));
+        assert!(!map.remove(&Key(5)));
+        assert_eq!(map.get(&Key(5)), None);
+        assert_eq!(map.get(&Key(8)), Some(&18));
+        unsafe {
+            assert!(map.set(&tvm.allocator, Key(5), 7));
+        }
+        assert_eq!(map.get(&Key(5)), Some(&7));
+        assert_eq!(map.iter().count(), 100);
+        assert_eq!(map.iter().map(|(key, _)| key.0).sum::<usize>(), 4950);
+        for i in 0..100 {
+            assert!(map.remove(&Key(i)));
+        }
+        assert!(map.is_empty());
+        unsafe {
+            map.destroy(&tvm.allocator);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
 }
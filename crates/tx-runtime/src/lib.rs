@@ -1,10 +1,24 @@
 #![feature(allocator_api)]
 #![feature(ptr_internals)]
-mod allocator;
+pub mod allocator;
+mod arithmetic;
 mod chunk;
+mod class;
+mod constant_folding;
 mod dyn_array;
 mod hash_map;
+mod list;
+mod map;
+mod object;
+#[cfg(feature = "debug-features")]
+mod opcode_stats;
 mod opcodes;
+mod optimizer;
+mod profiler;
+mod set;
+mod string;
+mod tuple;
 mod types;
 mod value;
-mod vm;
+mod verifier;
+pub mod vm;
@@ -0,0 +1,279 @@
+use std::cmp::Ordering;
+
+use crate::{
+    allocator::AllocCategory,
+    dyn_array::DynArray,
+    types::TxInt,
+    value::{compare_int_float, Value},
+    vm::{RuntimeError, VmAlloc, VM},
+};
+
+/// Growable list. Indices are Tx values: negative indices count from the
+/// end and out of range indices are a runtime error.
+pub struct TxList<'a> {
+    items: DynArray<'a, Value, VmAlloc>,
+}
+
+impl<'a> TxList<'a> {
+    pub fn new(tvm: &'a VM) -> Self {
+        Self {
+            items: DynArray::new(&tvm.allocator),
+        }
+    }
+
+    /// List literal, used by BUILD_LIST.
+    pub fn from_values(
+        tvm: &'a VM,
+        values: &[Value],
+    ) -> Result<Self, RuntimeError> {
+        let mut list = Self::new(tvm);
+        tvm.allocator
+            .with_category(AllocCategory::List, || unsafe {
+                list.items.try_extend_from_slice(&tvm.allocator, values)
+            })?;
+        Ok(list)
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::List, || {
+            self.items.destroy(&tvm.allocator);
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Value] {
+        &self.items
+    }
+
+    pub fn get(&self, index: Value) -> Result<Value, RuntimeError> {
+        let idx = get_index(index, self.len(), false)?;
+        Ok(self.items[idx])
+    }
+
+    pub fn set(
+        &mut self,
+        index: Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let idx = get_index(index, self.len(), false)?;
+        self.items[idx] = value;
+        Ok(())
+    }
+
+    /// Copy of `start..end`, either bound can be `Nil` to slice from the
+    /// start or to the end. Unlike indices, bounds can be equal to the
+    /// length but must still be in range and in order.
+    pub fn slice(
+        &self,
+        tvm: &'a VM,
+        start: Value,
+        end: Value,
+    ) -> Result<Self, RuntimeError> {
+        let len = self.len();
+        let start = match start {
+            Value::Nil => 0,
+            _ => get_index(start, len, true)?,
+        };
+        let end = match end {
+            Value::Nil => len,
+            _ => get_index(end, len, true)?,
+        };
+        if start > end {
+            return Err(RuntimeError::IndexOutOfRange);
+        }
+        Self::from_values(tvm, &self.items[start..end])
+    }
+
+    pub fn push(
+        &mut self,
+        tvm: &VM,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        tvm.allocator
+            .with_category(AllocCategory::List, || unsafe {
+                self.items.try_push(&tvm.allocator, value)
+            })?;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<Value, RuntimeError> {
+        self.items.pop().ok_or(RuntimeError::EmptyList)
+    }
+
+    /// Insert before `index`, that can be equal to the length to append.
+    pub fn insert(
+        &mut self,
+        tvm: &VM,
+        index: Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        let idx = get_index(index, self.len(), true)?;
+        tvm.allocator
+            .with_category(AllocCategory::List, || unsafe {
+                self.items.try_insert(&tvm.allocator, idx, value)
+            })?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: Value) -> Result<Value, RuntimeError> {
+        let idx = get_index(index, self.len(), false)?;
+        Ok(self.items.remove(idx))
+    }
+
+    pub fn contains(&self, value: Value) -> bool {
+        self.items.contains(&value)
+    }
+
+    pub fn reverse(&mut self) {
+        self.items.reverse();
+    }
+
+    /// Stable sort of a list of numbers or of chars. NaNs are sorted last.
+    pub fn sort(&mut self) -> Result<(), RuntimeError> {
+        let is_number =
+            |value: &Value| matches!(value, Value::Int(_) | Value::Float(_));
+        let is_char = |value: &Value| matches!(value, Value::Char(_));
+        if !self.items.iter().all(is_number) && !self.items.iter().all(is_char)
+        {
+            return Err(RuntimeError::OperandsNotComparable);
+        }
+        self.items.sort_by(total_cmp);
+        Ok(())
+    }
+}
+
+/// Index in `0..len`, or `0..=len` with `allow_end`, from a Tx index.
+/// Negative indices are from the end.
+pub(crate) fn get_index(
+    index: Value,
+    len: usize,
+    allow_end: bool,
+) -> Result<usize, RuntimeError> {
+    let Value::Int(index) = index else {
+        return Err(RuntimeError::IndexMustBeInteger);
+    };
+    let idx = if index < 0 {
+        (len as TxInt).checked_add(index)
+    } else {
+        Some(index)
+    };
+    match idx.and_then(|idx| usize::try_from(idx).ok()) {
+        Some(idx) if idx < len || (allow_end && idx == len) => Ok(idx),
+        _ => Err(RuntimeError::IndexOutOfRange),
+    }
+}
+
+// Total order for lists checked by `TxList::sort`
+fn total_cmp(lhs: &Value, rhs: &Value) -> Ordering {
+    let ordering = match (*lhs, *rhs) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(&b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(&b),
+        (Value::Int(i), Value::Float(f)) => compare_int_float(i, f),
+        (Value::Float(f), Value::Int(i)) => {
+            compare_int_float(i, f).map(Ordering::reverse)
+        }
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(&b)),
+        _ => unreachable!(),
+    };
+    let is_nan =
+        |value: &Value| matches!(value, Value::Float(f) if f.is_nan());
+    ordering.unwrap_or_else(|| is_nan(lhs).cmp(&is_nan(rhs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TxFloat;
+
+    #[test]
+    fn test_indexing() {
+        let tvm = VM::new();
+        let values = [1, 2, 3, 4].map(Value::Int);
+        let mut list = TxList::from_values(&tvm, &values).unwrap();
+        assert_eq!(list.get(Value::Int(0)), Ok(Value::Int(1)));
+        assert_eq!(list.get(Value::Int(-1)), Ok(Value::Int(4)));
+        assert_eq!(
+            list.get(Value::Int(4)),
+            Err(RuntimeError::IndexOutOfRange)
+        );
+        assert_eq!(
+            list.get(Value::Int(-5)),
+            Err(RuntimeError::IndexOutOfRange)
+        );
+        assert_eq!(
+            list.get(Value::Int(TxInt::MIN)),
+            Err(RuntimeError::IndexOutOfRange)
+        );
+        assert_eq!(
+            list.get(Value::Float(0.0)),
+            Err(RuntimeError::IndexMustBeInteger)
+        );
+        assert_eq!(list.set(Value::Int(-2), Value::Nil), Ok(()));
+        assert_eq!(
+            list.remove(Value::Int(4)),
+            Err(RuntimeError::IndexOutOfRange)
+        );
+        assert_eq!(list.remove(Value::Int(0)), Ok(Value::Int(1)));
+        assert_eq!(list.insert(&tvm, Value::Int(3), Value::Int(5)), Ok(()));
+        assert_eq!(
+            list.insert(&tvm, Value::Int(5), Value::Int(6)),
+            Err(RuntimeError::IndexOutOfRange)
+        );
+        assert_eq!(
+            list.as_slice(),
+            &[Value::Int(2), Value::Nil, Value::Int(4), Value::Int(5)]
+        );
+        let mut slice =
+            list.slice(&tvm, Value::Int(1), Value::Int(-1)).unwrap();
+        assert_eq!(slice.as_slice(), &[Value::Nil, Value::Int(4)]);
+        let mut empty = list.slice(&tvm, Value::Int(4), Value::Nil).unwrap();
+        assert!(empty.is_empty());
+        assert!(list.slice(&tvm, Value::Int(2), Value::Int(1)).is_err());
+        assert!(list.slice(&tvm, Value::Nil, Value::Int(5)).is_err());
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::List as usize].count, 2);
+        unsafe {
+            list.destroy(&tvm);
+            slice.destroy(&tvm);
+            empty.destroy(&tvm);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_methods() {
+        let tvm = VM::new();
+        let mut list = TxList::new(&tvm);
+        assert_eq!(list.pop(), Err(RuntimeError::EmptyList));
+        for value in [
+            Value::Float(TxFloat::NAN),
+            Value::Int(3),
+            Value::Float(-0.5),
+            Value::Int(1),
+        ] {
+            list.push(&tvm, value).unwrap();
+        }
+        assert!(list.contains(Value::Float(3.0)));
+        assert!(!list.contains(Value::Int(2)));
+        assert_eq!(list.sort(), Ok(()));
+        assert_eq!(list.get(Value::Int(0)), Ok(Value::Float(-0.5)));
+        assert_eq!(list.get(Value::Int(2)), Ok(Value::Int(3)));
+        list.reverse();
+        assert!(matches!(list.pop(), Ok(Value::Float(f)) if f == -0.5));
+        assert!(
+            matches!(list.remove(Value::Int(0)), Ok(Value::Float(f)) if f.is_nan())
+        );
+        list.push(&tvm, Value::Char('a')).unwrap();
+        assert_eq!(list.sort(), Err(RuntimeError::OperandsNotComparable));
+        unsafe {
+            list.destroy(&tvm);
+        }
+    }
+}
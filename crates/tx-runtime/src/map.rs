@@ -0,0 +1,296 @@
+use crate::{
+    allocator::AllocCategory,
+    dyn_array::DynArray,
+    hash_map::HashMap,
+    value::Value,
+    vm::{RuntimeError, VmAlloc, VM},
+};
+
+/// Dictionary with `Value` keys. Keys compare with `==`, so `1` and `1.0`
+/// are the same key.
+///
+/// Iteration follows insertion order: entries are stored in an array and
+/// the hash map only maps keys to indices in it. Removed entries leave a
+/// hole, reclaimed when holes outnumber the live entries. Updating a key
+/// keeps its position and the key it was first inserted with.
+pub struct TxMap<'a> {
+    entries: DynArray<'a, MapEntry, VmAlloc>,
+    indices: HashMap<'a, Value, usize, VmAlloc>,
+    len: usize,
+}
+
+#[derive(Clone, Copy)]
+struct MapEntry {
+    key: Value, // Value::None for removed entries
+    value: Value,
+}
+
+impl<'a> TxMap<'a> {
+    const MIN_HOLES_TO_COMPACT: usize = 8;
+
+    pub fn new(tvm: &'a VM) -> Self {
+        Self {
+            entries: DynArray::new(&tvm.allocator),
+            indices: HashMap::new(&tvm.allocator),
+            len: 0,
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::Map, || {
+            self.entries.destroy(&tvm.allocator);
+            self.indices.destroy(&tvm.allocator);
+        });
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// `map[key]`, a missing key is an error.
+    pub fn get(&self, key: Value) -> Result<Value, RuntimeError> {
+        let idx = self.get_index(key)?.ok_or(RuntimeError::KeyNotFound)?;
+        Ok(self.entries[idx].value)
+    }
+
+    /// `key in map`
+    pub fn contains(&self, key: Value) -> Result<bool, RuntimeError> {
+        Ok(self.get_index(key)?.is_some())
+    }
+
+    /// `map[key] = value`
+    pub fn set(
+        &mut self,
+        tvm: &VM,
+        key: Value,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if let Some(idx) = self.get_index(key)? {
+            self.entries[idx].value = value;
+            return Ok(());
+        }
+        let idx = self.entries.len();
+        tvm.allocator.with_category(AllocCategory::Map, || unsafe {
+            self.entries
+                .try_push(&tvm.allocator, MapEntry { key, value })?;
+            let result = self.indices.try_set(&tvm.allocator, key, idx);
+            if result.is_err() {
+                self.entries.pop();
+            }
+            result
+        })?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove a key and return its value, a missing key is an error.
+    pub fn remove(
+        &mut self,
+        tvm: &VM,
+        key: Value,
+    ) -> Result<Value, RuntimeError> {
+        let idx = self.get_index(key)?.ok_or(RuntimeError::KeyNotFound)?;
+        let entry = &mut self.entries[idx];
+        let value = entry.value;
+        entry.key = Value::None;
+        entry.value = Value::Nil;
+        self.indices.remove(&key);
+        self.len -= 1;
+        let holes = self.entries.len() - self.len;
+        if holes >= Self::MIN_HOLES_TO_COMPACT && holes > self.len {
+            self.compact(tvm)?;
+        }
+        Ok(value)
+    }
+
+    /// Iterate over the key/value pairs, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (Value, Value)> + '_ {
+        self.entries
+            .iter()
+            .filter(|entry| !matches!(entry.key, Value::None))
+            .map(|entry| (entry.key, entry.value))
+    }
+
+    fn get_index(&self, key: Value) -> Result<Option<usize>, RuntimeError> {
+        if !key.is_hashable() {
+            return Err(RuntimeError::UnhashableKey);
+        }
+        Ok(self.indices.get(&key).copied())
+    }
+
+    // Move the live entries over the holes, keeping their order, and
+    // release the memory they used
+    fn compact(&mut self, tvm: &VM) -> Result<(), RuntimeError> {
+        let mut live = 0;
+        for idx in 0..self.entries.len() {
+            let entry = self.entries[idx];
+            if matches!(entry.key, Value::None) {
+                continue;
+            }
+            self.entries[live] = entry;
+            // Only updates existing keys, so does not allocate
+            tvm.allocator.with_category(AllocCategory::Map, || unsafe {
+                self.indices.try_set(&tvm.allocator, entry.key, live)
+            })?;
+            live += 1;
+        }
+        while self.entries.len() > live {
+            self.entries.pop();
+        }
+        // Only an optimization, so failing to shrink is not an error
+        tvm.allocator.with_category(AllocCategory::Map, || unsafe {
+            let _ = self.entries.try_shrink_to_fit(&tvm.allocator);
+            let _ = self.indices.try_shrink_to_fit(&tvm.allocator);
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        list::TxList,
+        object::{free_object, new_object, Object},
+        string::TxString,
+        tuple::TxTuple,
+        types::TxFloat,
+    };
+
+    #[test]
+    fn test_map() {
+        let tvm = VM::new();
+        let mut map = TxMap::new(&tvm);
+        let keys = [
+            Value::Int(1),
+            Value::Char('a'),
+            Value::Bool(false),
+            Value::Nil,
+            Value::Float(0.5),
+        ];
+        for (i, &key) in keys.iter().enumerate() {
+            assert_eq!(map.set(&tvm, key, Value::Int(i as _)), Ok(()));
+        }
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.set(&tvm, Value::Float(1.0), Value::Nil), Ok(()));
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(Value::Int(1)), Ok(Value::Nil));
+        assert_eq!(map.get(Value::Char('a')), Ok(Value::Int(1)));
+        assert_eq!(map.get(Value::Char('b')), Err(RuntimeError::KeyNotFound));
+        assert_eq!(map.contains(Value::Nil), Ok(true));
+        assert_eq!(
+            map.set(&tvm, Value::Float(TxFloat::NAN), Value::Nil),
+            Err(RuntimeError::UnhashableKey)
+        );
+        assert_eq!(
+            map.contains(Value::Float(TxFloat::NAN)),
+            Err(RuntimeError::UnhashableKey)
+        );
+        assert_eq!(map.remove(&tvm, Value::Bool(false)), Ok(Value::Int(2)));
+        assert_eq!(
+            map.remove(&tvm, Value::Bool(false)),
+            Err(RuntimeError::KeyNotFound)
+        );
+        assert_eq!(map.len(), 4);
+        let pairs = [
+            (Value::Int(1), Value::Nil),
+            (Value::Char('a'), Value::Int(1)),
+            (Value::Nil, Value::Int(3)),
+            (Value::Float(0.5), Value::Int(4)),
+        ];
+        assert!(map.iter().eq(pairs));
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::Map as usize].count, 2);
+        unsafe {
+            map.destroy(&tvm);
+        }
+        assert!(map.is_empty());
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let tvm = VM::new();
+        let mut map = TxMap::new(&tvm);
+        for i in 0..100 {
+            map.set(&tvm, Value::Int(i), Value::Int(-i)).unwrap();
+        }
+        for i in (0..100).filter(|i| i % 10 != 0) {
+            assert_eq!(map.remove(&tvm, Value::Int(i)), Ok(Value::Int(-i)));
+        }
+        // Compacted along the way, then re-inserting appends
+        assert!(map.entries.len() < 100);
+        let bytes = tvm.allocator.allocated_bytes();
+        for i in [30, 40, 60, 70, 80, 90, 10, 20, 50] {
+            map.remove(&tvm, Value::Int(i)).unwrap();
+        }
+        assert!(tvm.allocator.allocated_bytes() < bytes / 2);
+        for i in [10, 20, 30, 40, 50, 60, 70, 80, 90] {
+            map.set(&tvm, Value::Int(i), Value::Int(-i)).unwrap();
+        }
+        map.remove(&tvm, Value::Int(0)).unwrap();
+        map.set(&tvm, Value::Int(0), Value::Nil).unwrap();
+        map.set(&tvm, Value::Int(50), Value::Nil).unwrap();
+        let keys: Vec<_> = map.iter().map(|(key, _)| key).collect();
+        let expected: Vec<_> = [10, 20, 30, 40, 50, 60, 70, 80, 90, 0]
+            .map(Value::Int)
+            .into();
+        assert_eq!(keys, expected);
+        assert_eq!(map.get(Value::Int(90)), Ok(Value::Int(-90)));
+        assert_eq!(map.len(), 10);
+        unsafe {
+            map.destroy(&tvm);
+        }
+    }
+
+    #[test]
+    fn test_object_keys() {
+        let tvm = VM::new();
+        let tuple = |values: &[_]| {
+            let tuple = TxTuple::new(&tvm, values).unwrap();
+            new_object(&tvm, Object::Tuple(tuple)).unwrap()
+        };
+        let string = |string| {
+            let string = TxString::new(&tvm, string).unwrap();
+            new_object(&tvm, Object::String(string)).unwrap()
+        };
+        let list = TxList::new(&tvm);
+        let objects = [
+            tuple(&[Value::Int(1), Value::Int(2)]),
+            tuple(&[Value::Int(1), Value::Int(2)]),
+            tuple(&[Value::Float(1.0), Value::Float(2.0)]),
+            string("key"),
+            string("key"),
+            tuple(&[Value::Float(TxFloat::NAN)]),
+            new_object(&tvm, Object::List(list)).unwrap(),
+        ];
+        let [a, b, floats, key, other_key, nan, list] = objects;
+        let mut map = TxMap::new(&tvm);
+        map.set(&tvm, a, Value::Int(1)).unwrap();
+        assert_eq!(map.get(b), Ok(Value::Int(1)));
+        assert_eq!(map.get(floats), Ok(Value::Int(1)));
+        map.set(&tvm, key, Value::Int(2)).unwrap();
+        assert_eq!(map.get(other_key), Ok(Value::Int(2)));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(nan), Err(RuntimeError::UnhashableKey));
+        assert_eq!(
+            map.set(&tvm, list, Value::Nil),
+            Err(RuntimeError::UnhashableKey)
+        );
+        unsafe {
+            map.destroy(&tvm);
+            for object in objects {
+                let Value::Object(obj) = object else {
+                    unreachable!()
+                };
+                free_object(&tvm, obj);
+            }
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{fmt, ptr::NonNull};
+
+use crate::{
+    allocator::AllocCategory,
+    class::{TxBoundMethod, TxClass, TxInstance, TxTrait},
+    list::TxList,
+    map::TxMap,
+    set::TxSet,
+    string::TxString,
+    tuple::TxTuple,
+    value::Value,
+    vm::{RuntimeError, VM},
+};
+
+/// Heap object referenced by `Value::Object`.
+pub enum Object<'a> {
+    String(TxString<'a>),
+    Tuple(TxTuple<'a>),
+    List(TxList<'a>),
+    Map(TxMap<'a>),
+    Set(TxSet<'a>),
+    Class(TxClass<'a>),
+    Instance(TxInstance<'a>),
+    BoundMethod(TxBoundMethod),
+    Trait(TxTrait<'a>),
+}
+
+impl Object<'_> {
+    fn get_category(&self) -> AllocCategory {
+        match self {
+            Self::String(_) => AllocCategory::String,
+            Self::Tuple(_) => AllocCategory::Tuple,
+            Self::List(_) => AllocCategory::List,
+            Self::Map(_) | Self::Set(_) => AllocCategory::Map,
+            Self::Class(_)
+            | Self::Instance(_)
+            | Self::BoundMethod(_)
+            | Self::Trait(_) => AllocCategory::Class,
+        }
+    }
+
+    unsafe fn destroy(&mut self, tvm: &VM) {
+        match self {
+            Self::String(string) => string.destroy(tvm),
+            Self::Tuple(tuple) => tuple.destroy(tvm),
+            Self::List(list) => list.destroy(tvm),
+            Self::Map(map) => map.destroy(tvm),
+            Self::Set(set) => set.destroy(tvm),
+            Self::Class(class) => class.destroy(tvm),
+            Self::Instance(instance) => instance.destroy(tvm),
+            Self::BoundMethod(_) => (),
+            Self::Trait(trait_) => trait_.destroy(tvm),
+        }
+    }
+}
+
+/// Reference to a heap object, only created by `new_object`. The object
+/// stays valid until freed with `free_object`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ObjRef(NonNull<Object<'static>>);
+
+impl ObjRef {
+    pub fn get(&self) -> &Object<'_> {
+        // Values are not used once their object is freed
+        unsafe { self.0.as_ref() }
+    }
+
+    /// # Safety
+    ///
+    /// No other reference to the object may be alive while the returned
+    /// one is used.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_mut(&self) -> &mut Object<'_> {
+        &mut *self.0.as_ptr()
+    }
+
+    pub(crate) fn addr(self) -> usize {
+        self.0.as_ptr() as usize
+    }
+}
+
+impl fmt::Debug for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ObjRef({:#x})", self.addr())
+    }
+}
+
+/// Move `object` to the heap and return the value referencing it. The
+/// object is destroyed if the memory can not be allocated.
+pub fn new_object<'a>(
+    tvm: &'a VM,
+    mut object: Object<'a>,
+) -> Result<Value, RuntimeError> {
+    let category = object.get_category();
+    let allocated = tvm
+        .allocator
+        .with_category(category, || Box::try_new_uninit_in(&tvm.allocator));
+    let Ok(uninit) = allocated else {
+        unsafe {
+            object.destroy(tvm);
+        }
+        return Err(RuntimeError::OutOfMemory);
+    };
+    let (ptr, _) = Box::into_raw_with_allocator(Box::write(uninit, object));
+    // The lifetime is erased, objects do not outlive the VM
+    let ptr = unsafe { NonNull::new_unchecked(ptr) }.cast();
+    Ok(Value::Object(ObjRef(ptr)))
+}
+
+/// Destroy the object and free its memory.
+///
+/// # Safety
+///
+/// `obj` must have been created by `new_object` with the same VM and
+/// must not be used afterwards, through any copy of the value.
+pub unsafe fn free_object(tvm: &VM, obj: ObjRef) {
+    let mut object =
+        Box::from_raw_in(obj.0.cast::<Object>().as_ptr(), &tvm.allocator);
+    object.destroy(tvm);
+    tvm.allocator
+        .with_category(object.get_category(), || drop(object));
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+
+use crate::opcodes::{OpCode, OPCODE_COUNT};
+
+/// Execution counts of each opcode and of each pair of consecutive
+/// opcodes, to find candidates for superinstructions and specializations.
+pub struct OpcodeStats {
+    counts: [u64; OPCODE_COUNT],
+    pair_counts: Vec<u64>,
+    previous: Option<OpCode>,
+}
+
+impl OpcodeStats {
+    const MAX_PAIRS_DISPLAYED: usize = 20;
+
+    pub fn new() -> Self {
+        Self {
+            counts: [0; OPCODE_COUNT],
+            pair_counts: vec![0; OPCODE_COUNT * OPCODE_COUNT],
+            previous: None,
+        }
+    }
+
+    /// To be called by the dispatch loop before executing an instruction.
+    pub fn record(&mut self, opc: OpCode) {
+        let idx = usize::from(u8::from(opc));
+        self.counts[idx] += 1;
+        if let Some(previous) = self.previous {
+            let previous_idx = usize::from(u8::from(previous));
+            self.pair_counts[previous_idx * OPCODE_COUNT + idx] += 1;
+        }
+        self.previous = Some(opc);
+    }
+
+    /// Break the sequence so the next opcode does not form a pair with the
+    /// last one, e.g. when switching call frames.
+    pub fn break_sequence(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn get_count(&self, opc: OpCode) -> u64 {
+        self.counts[usize::from(u8::from(opc))]
+    }
+
+    pub fn get_pair_count(&self, first: OpCode, second: OpCode) -> u64 {
+        let first_idx = usize::from(u8::from(first));
+        let second_idx = usize::from(u8::from(second));
+        self.pair_counts[first_idx * OPCODE_COUNT + second_idx]
+    }
+}
+
+impl Default for OpcodeStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn opcode(idx: usize) -> OpCode {
+    OpCode::try_from(idx as u8).unwrap()
+}
+
+impl fmt::Display for OpcodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: u64 = self.counts.iter().sum();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        let mut counts: Vec<_> = (0..OPCODE_COUNT)
+            .filter(|&idx| self.counts[idx] != 0)
+            .map(|idx| (self.counts[idx], opcode(idx).get_name()))
+            .collect();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        writeln!(f, "== opcode counts ({total} executed) ==")?;
+        for (count, name) in counts {
+            writeln!(f, "{name:<20} {count:>12} {:>6.2}%", percent(count))?;
+        }
+        let mut pairs: Vec<_> = (0..OPCODE_COUNT * OPCODE_COUNT)
+            .filter(|&idx| self.pair_counts[idx] != 0)
+            .map(|idx| {
+                let first = opcode(idx / OPCODE_COUNT).get_name();
+                let second = opcode(idx % OPCODE_COUNT).get_name();
+                (self.pair_counts[idx], first, second)
+            })
+            .collect();
+        pairs.sort_unstable_by(|a, b| b.cmp(a));
+        writeln!(f, "== most common opcode pairs ==")?;
+        for (count, first, second) in
+            pairs.into_iter().take(Self::MAX_PAIRS_DISPLAYED)
+        {
+            let pair = format!("{first} {second}");
+            writeln!(f, "{pair:<41} {count:>12} {:>6.2}%", percent(count))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::*;
+
+    #[test]
+    fn test_record() {
+        let mut stats = OpcodeStats::new();
+        for opc in [NIL, POP, NIL, POP, RETURN] {
+            stats.record(opc);
+        }
+        stats.break_sequence();
+        stats.record(NIL);
+        assert_eq!(stats.get_count(NIL), 3);
+        assert_eq!(stats.get_count(ADD), 0);
+        assert_eq!(stats.get_pair_count(NIL, POP), 2);
+        assert_eq!(stats.get_pair_count(POP, NIL), 1);
+        assert_eq!(stats.get_pair_count(RETURN, NIL), 0);
+        let report = stats.to_string();
+        let nil_line = report.lines().nth(1).unwrap();
+        assert!(nil_line.starts_with("NIL "));
+        assert!(nil_line.ends_with("50.00%"));
+        assert!(report.contains("NIL POP "));
+    }
+}
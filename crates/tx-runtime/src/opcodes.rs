@@ -5,6 +5,7 @@ macro_rules! opcodes {
         $crate::__opcodes!((0) $($opc,)*);
         $crate::__operands!($($num_operands),*);
         $crate::__stack_effects!($($stack_effect),*);
+        $crate::__names!($($opc),*);
     };
 }
 
@@ -35,7 +36,17 @@ macro_rules! __stack_effects {
     };
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[macro_export]
+#[doc(hidden)]
+macro_rules! __names {
+    ( $($opc:ident),* ) => {
+        const OPCODE_NAMES: &'static [&'static str] = &[$(stringify!($opc)),*];
+    };
+}
+
+pub const OPCODE_COUNT: usize = OPCODE_NUM_OPERANDS.len();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct OpCode(u8);
 
 impl OpCode {
@@ -46,6 +57,10 @@ impl OpCode {
     pub const fn get_stack_effect(&self) -> isize {
         OPCODE_STACK_EFFECT[self.0 as usize]
     }
+
+    pub const fn get_name(&self) -> &'static str {
+        OPCODE_NAMES[self.0 as usize]
+    }
 }
 
 impl From<OpCode> for u8 {
@@ -54,6 +69,29 @@ impl From<OpCode> for u8 {
     }
 }
 
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        if usize::from(byte) < OPCODE_COUNT {
+            Ok(OpCode(byte))
+        } else {
+            Err(byte)
+        }
+    }
+}
+
+/// Split the operand of INVOKE, SUPER_INVOKE and their _LONG variants into
+/// the constant index of the method name and the argument count, stored
+/// in the last byte.
+pub const fn split_invoke_operand(
+    opc: OpCode,
+    operand: usize,
+) -> (usize, usize) {
+    let name_bits = (opc.get_num_operands() - 1) * 8;
+    (operand & ((1 << name_bits) - 1), operand >> name_bits)
+}
+
 opcodes! {
     (CONSTANT,             1, 1),
     (CONSTANT_LONG,        3, 1),
@@ -85,16 +123,60 @@ opcodes! {
     (SUBSTRACT,            0, -1),
     (MULTIPLY,             0, -1),
     (DIVIDE,               0, -1),
+    (INT_DIVIDE,           0, -1),
+    (MODULO,               0, -1),
+    (POWER,                0, -1),
+    (BIT_AND,              0, -1),
+    (BIT_OR,               0, -1),
+    (BIT_XOR,              0, -1),
+    (SHIFT_LEFT,           0, -1),
+    (SHIFT_RIGHT,          0, -1),
     (NOT,                  0, 0),
     (NEGATE,               0, 0),
+    (BIT_NOT,              0, 0),
     (JUMP,                 2, 0),
     (JUMP_IF_FALSE,        2, 0),
+    (JUMP_IF_TRUE,         2, 0),
     (LOOP,                 2, 0),
     (CALL,                 1, 0), // 0 for tx fn's but for native fn it is the same as RETURN
     (CLOSURE,              1, 1),
     (CLOSURE_LONG,         3, 1),
+    (CLASS,                1, 1),
+    (CLASS_LONG,           3, 1),
+    (METHOD,               1, -1),
+    (METHOD_LONG,          3, -1),
+    (GET_PROPERTY,         1, 0),
+    (GET_PROPERTY_LONG,    3, 0),
+    (SET_PROPERTY,         1, -1),
+    (SET_PROPERTY_LONG,    3, -1),
+    (INVOKE,               2, 0), // Same as CALL, see split_invoke_operand
+    (INVOKE_LONG,          4, 0), // Same as CALL, see split_invoke_operand
+    (INHERIT,              0, -1),
+    (GET_SUPER,            1, -1),
+    (GET_SUPER_LONG,       3, -1),
+    (SUPER_INVOKE,         2, -1), // Same as INVOKE but pops the superclass
+    (SUPER_INVOKE_LONG,    4, -1), // Same as INVOKE but pops the superclass
+    (IS,                   0, -1),
+    (TRAIT,                1, 1),
+    (TRAIT_LONG,           3, 1),
+    (IMPLEMENT,            0, -1),
+    (IMPLEMENTS,           0, -1),
     (END_SCOPE,            1, 0), // Stack effect is in the operand
     (END_SCOPE_LONG,       3, 0), // Stack effect is in the operand
+    (BUILD_STRING,         1, 0), // Stack effect is in the operand
+    (BUILD_LIST,           1, 0), // Stack effect is in the operand
+    (BUILD_LIST_LONG,      3, 0), // Stack effect is in the operand
+    (BUILD_MAP,            1, 0), // Stack effect is in the operand
+    (BUILD_MAP_LONG,       3, 0), // Stack effect is in the operand
+    (BUILD_SET,            1, 0), // Stack effect is in the operand
+    (BUILD_SET_LONG,       3, 0), // Stack effect is in the operand
+    (BUILD_TUPLE,          1, 0), // Stack effect is in the operand
+    (UNPACK_TUPLE,         1, 0), // Stack effect is in the operand
+    (GET_INDEX,            0, -1),
+    (SET_INDEX,            0, -2),
+    (DELETE_INDEX,         0, -2),
+    (IN,                   0, -1),
+    (GET_SLICE,            0, -2),
     (RETURN,               0, 0), // Stack effect is variable
     // (END,                  0, 0),
 }
@@ -115,10 +197,35 @@ mod tests {
         assert_eq!(POP.get_num_operands(), 0);
     }
 
+    #[test]
+    fn test_split_invoke_operand() {
+        assert_eq!(split_invoke_operand(INVOKE, 0x0305), (5, 3));
+        assert_eq!(split_invoke_operand(SUPER_INVOKE, 0x0007), (7, 0));
+        assert_eq!(
+            split_invoke_operand(INVOKE_LONG, 0x0201_0005),
+            (0x10005, 2)
+        );
+    }
+
     #[test]
     fn test_stack_effect() {
         assert_eq!(CONSTANT.get_stack_effect(), 1);
         assert_eq!(CONSTANT_LONG.get_stack_effect(), 1);
         assert_eq!(POP.get_stack_effect(), -1);
     }
+
+    #[test]
+    fn test_name() {
+        assert_eq!(CONSTANT.get_name(), "CONSTANT");
+        assert_eq!(RETURN.get_name(), "RETURN");
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(OpCode::try_from(0), Ok(CONSTANT));
+        assert_eq!(OpCode::try_from(u8::from(RETURN)), Ok(RETURN));
+        let count = OPCODE_COUNT as u8;
+        assert_eq!(OpCode::try_from(count), Err(count));
+        assert_eq!(OpCode::try_from(0xff), Err(0xff));
+    }
 }
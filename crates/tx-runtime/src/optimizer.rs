@@ -0,0 +1,504 @@
+use crate::{
+    allocator::AllocCategory,
+    chunk::{read_multibyte_operand, Chunk, SpanStart},
+    opcodes::*,
+    vm::VM,
+};
+
+#[derive(Clone, Copy)]
+struct Instruction {
+    // Offset in the original bytecode, used as a stable identifier
+    id: usize,
+    opc: OpCode,
+    operand: usize,
+    // Identifier of the instruction jumped to, if any
+    target: Option<usize>,
+}
+
+/// Peephole optimization pass over the bytecode of a verified chunk.
+///
+/// Removes redundant instruction sequences, then re-encodes the bytecode
+/// with updated jump offsets and span table. Instructions that are jump
+/// targets are never removed.
+pub fn optimize(tvm: &VM, chunk: &mut Chunk) {
+    let mut code = decode(chunk);
+    loop {
+        let threaded = thread_jumps(&mut code);
+        let (new_code, removed) = remove_redundant(&code);
+        code = new_code;
+        if !threaded && !removed {
+            break;
+        }
+    }
+    encode(tvm, chunk, &code);
+}
+
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+    let bytecode = &chunk.bytecode[..];
+    let mut code = Vec::new();
+    let mut offset = 0;
+    while offset < bytecode.len() {
+        let opc = OpCode::try_from(bytecode[offset]).unwrap();
+        let next = offset + 1 + opc.get_num_operands();
+        let operand = read_multibyte_operand(&bytecode[offset + 1..next]);
+        let target = match opc {
+            JUMP | JUMP_IF_FALSE | JUMP_IF_TRUE => Some(next + operand),
+            LOOP => Some(next - operand),
+            _ => None,
+        };
+        code.push(Instruction {
+            id: offset,
+            opc,
+            operand,
+            target,
+        });
+        offset = next;
+    }
+    code
+}
+
+// Lookups by instruction identifier, built once per pass. Instructions
+// are only ever removed, so the identifiers stay in the original range.
+struct CodeIndex {
+    positions: Vec<Option<usize>>,
+    is_target: Vec<bool>,
+}
+
+impl CodeIndex {
+    fn new(code: &[Instruction]) -> Self {
+        let len = code
+            .iter()
+            .map(|instr| instr.id.max(instr.target.unwrap_or(0)) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut index = Self {
+            positions: vec![None; len],
+            is_target: vec![false; len],
+        };
+        for (idx, instr) in code.iter().enumerate() {
+            index.positions[instr.id] = Some(idx);
+            if let Some(target) = instr.target {
+                index.is_target[target] = true;
+            }
+        }
+        index
+    }
+
+    fn is_target(&self, id: usize) -> bool {
+        self.is_target[id]
+    }
+
+    fn find<'a>(
+        &self,
+        code: &'a [Instruction],
+        id: usize,
+    ) -> Option<&'a Instruction> {
+        self.positions
+            .get(id)
+            .copied()
+            .flatten()
+            .map(|idx| &code[idx])
+    }
+}
+
+// Largest jump offset that fits in the 2 bytes operand
+const MAX_JUMP: usize = u16::MAX as usize;
+
+// Forward jumps landing on an unconditional forward jump go directly to
+// its destination, unless the offset would no longer fit in the operand.
+fn thread_jumps(code: &mut [Instruction]) -> bool {
+    let index = CodeIndex::new(code);
+    let mut changed = false;
+    for i in 0..code.len() {
+        if !matches!(code[i].opc, JUMP | JUMP_IF_FALSE | JUMP_IF_TRUE) {
+            continue;
+        }
+        let Some(mut target) = code[i].target else {
+            continue;
+        };
+        // Identifiers are original offsets and the code only ever shrinks,
+        // so the encoded offset is at most this
+        let next = code[i].id + 1 + code[i].opc.get_num_operands();
+        // Forward jumps only, so following the chain always terminates
+        while let Some(instr) = index.find(code, target) {
+            match instr.target {
+                Some(further)
+                    if instr.opc == JUMP
+                        && further > target
+                        && further - next <= MAX_JUMP =>
+                {
+                    target = further;
+                }
+                _ => break,
+            }
+        }
+        if code[i].target != Some(target) {
+            code[i].target = Some(target);
+            changed = true;
+        }
+    }
+    changed
+}
+
+fn is_get_of_set(set: &Instruction, get: &Instruction) -> bool {
+    let same_kind = matches!(
+        (set.opc, get.opc),
+        (SET_LOCAL | SET_LOCAL_LONG, GET_LOCAL | GET_LOCAL_LONG)
+            | (
+                SET_UPVALUE | SET_UPVALUE_LONG,
+                GET_UPVALUE | GET_UPVALUE_LONG
+            )
+            | (SET_GLOBAL | SET_GLOBAL_LONG, GET_GLOBAL | GET_GLOBAL_LONG)
+    );
+    same_kind && set.operand == get.operand
+}
+
+// Returns the code without the redundant sequences, and whether any was
+// found. Rewritten code is only matched again on the next pass.
+fn remove_redundant(code: &[Instruction]) -> (Vec<Instruction>, bool) {
+    let index = CodeIndex::new(code);
+    let mut optimized = Vec::with_capacity(code.len());
+    let mut i = 0;
+    while i < code.len() {
+        let removable =
+            |n: usize| i + n < code.len() && !index.is_target(code[i + n].id);
+        // SET x; POP; GET x => SET x
+        if removable(1)
+            && removable(2)
+            && code[i + 1].opc == POP
+            && is_get_of_set(&code[i], &code[i + 2])
+        {
+            optimized.push(code[i]);
+            i += 3;
+            continue;
+        }
+        // NOT; JUMP_IF_FALSE => JUMP_IF_TRUE, when the condition is popped
+        // on both paths
+        if code[i].opc == NOT
+            && removable(1)
+            && code[i + 1].opc == JUMP_IF_FALSE
+            && code.get(i + 2).map(|instr| instr.opc) == Some(POP)
+            && code[i + 1]
+                .target
+                .and_then(|target| index.find(code, target))
+                .map(|instr| instr.opc)
+                == Some(POP)
+        {
+            // Keep the identifier of the first instruction of the sequence
+            optimized.push(Instruction {
+                id: code[i].id,
+                opc: JUMP_IF_TRUE,
+                ..code[i + 1]
+            });
+            i += 2;
+            continue;
+        }
+        // BUILD_TUPLE n; UNPACK_TUPLE n => nothing, for `let (a, b) = (x, y)`
+        if code[i].opc == BUILD_TUPLE
+            && removable(0)
+            && removable(1)
+            && code[i + 1].opc == UNPACK_TUPLE
+            && code[i + 1].operand == code[i].operand
+        {
+            i += 2;
+            continue;
+        }
+        // Jump to the next instruction
+        if code[i].opc == JUMP
+            && removable(0)
+            && code.get(i + 1).map(|instr| instr.id) == code[i].target
+        {
+            i += 1;
+            continue;
+        }
+        optimized.push(code[i]);
+        i += 1;
+    }
+    let changed = optimized.len() != code.len();
+    (optimized, changed)
+}
+
+fn encode(tvm: &VM, chunk: &mut Chunk, code: &[Instruction]) {
+    let mut new_offsets = Vec::with_capacity(code.len());
+    let mut len = 0;
+    for instr in code {
+        new_offsets.push(len);
+        len += 1 + instr.opc.get_num_operands();
+    }
+    // Identifiers are in increasing order
+    let new_offset_of = |id: usize| {
+        let idx = code.partition_point(|instr| instr.id < id);
+        new_offsets.get(idx).copied().unwrap_or(len)
+    };
+    let mut bytecode = Vec::with_capacity(len);
+    for (instr, &offset) in code.iter().zip(&new_offsets) {
+        let num_operands = instr.opc.get_num_operands();
+        let next = offset + 1 + num_operands;
+        let operand = match (instr.opc, instr.target) {
+            (LOOP, Some(target)) => next - new_offset_of(target),
+            (_, Some(target)) => new_offset_of(target) - next,
+            (_, None) => instr.operand,
+        };
+        // Guaranteed by `thread_jumps`, a truncated offset would jump to
+        // the wrong instruction
+        assert!(operand < (1 << (num_operands * 8)), "operand overflow");
+        bytecode.push(instr.opc.into());
+        for i in 0..num_operands {
+            bytecode.push(((operand >> (i * 8)) & 0xff) as u8);
+        }
+    }
+    tvm.allocator
+        .with_category(AllocCategory::Chunk, || unsafe {
+            chunk.bytecode.resize(&tvm.allocator, len, 0);
+        });
+    chunk.bytecode.copy_from_slice(&bytecode);
+    // Spans of removed instructions move to the next instruction, the last
+    // one at a given offset wins.
+    let mut moved: Vec<SpanStart> = Vec::new();
+    for span_start in chunk.span_starts() {
+        let offset = new_offset_of(span_start.offset);
+        if moved.last().map(|last| last.offset) == Some(offset) {
+            moved.pop();
+        }
+        moved.push(SpanStart {
+            offset,
+            span: span_start.span,
+        });
+    }
+    let mut spans: Vec<SpanStart> = Vec::new();
+    for span_start in moved {
+        if spans.last().map(|last| last.span) != Some(span_start.span)
+            && span_start.offset < len
+        {
+            spans.push(span_start);
+        }
+    }
+    chunk.set_span_starts(tvm, &spans);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Span, verifier::verify, vm::RuntimeError};
+
+    fn line(line: u32) -> Span {
+        Span {
+            file_id: 0,
+            line,
+            column: 1,
+            len: 1,
+        }
+    }
+
+    fn assert_optimized(
+        build: impl Fn(&VM, &mut Chunk) -> Result<(), RuntimeError>,
+        expected: &[u8],
+    ) {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        build(&tvm, &mut chunk).unwrap();
+        let depth = verify(&chunk);
+        optimize(&tvm, &mut chunk);
+        assert_eq!(&chunk.bytecode[..], expected);
+        assert_eq!(verify(&chunk), depth);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+
+    #[test]
+    fn test_set_pop_get() {
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), TRUE, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), SET_LOCAL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), POP, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), GET_LOCAL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                Ok(())
+            },
+            &[NIL.into(), TRUE.into(), SET_LOCAL.into(), 0, RETURN.into()],
+        );
+    }
+
+    #[test]
+    fn test_set_pop_get_other_slot() {
+        let expected = [
+            NIL.into(),
+            NIL.into(),
+            SET_LOCAL.into(),
+            0,
+            POP.into(),
+            GET_LOCAL.into(),
+            1,
+            RETURN.into(),
+        ];
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), SET_LOCAL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), POP, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), GET_LOCAL, 1)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                Ok(())
+            },
+            &expected,
+        );
+    }
+
+    #[test]
+    fn test_jump_to_jump() {
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), TRUE, 0)?;
+                chunk.write_instruction::<2>(
+                    tvm,
+                    line(1),
+                    JUMP_IF_FALSE,
+                    2,
+                )?;
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                chunk.write_instruction::<2>(tvm, line(1), JUMP, 1)?;
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                Ok(())
+            },
+            &[
+                TRUE.into(),
+                JUMP_IF_FALSE.into(),
+                6,
+                0,
+                NIL.into(),
+                RETURN.into(),
+                JUMP.into(),
+                1,
+                0,
+                NIL.into(),
+                RETURN.into(),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_jump_to_jump_overflow() {
+        // Threading would make the first jump 3 bytes longer than the
+        // second one, that is already as long as it can be
+        let build = |tvm: &VM, chunk: &mut Chunk| {
+            chunk.write_instruction::<0>(tvm, line(1), TRUE, 0)?;
+            chunk.write_instruction::<2>(tvm, line(1), JUMP_IF_FALSE, 0)?;
+            chunk.write_instruction::<2>(tvm, line(1), JUMP, MAX_JUMP)?;
+            chunk.write_instruction::<1>(tvm, line(1), GET_LOCAL, 0)?;
+            chunk.write_instruction::<0>(tvm, line(1), POP, 0)?;
+            for _ in 0..(MAX_JUMP - 3) / 2 {
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), POP, 0)?;
+            }
+            chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)
+        };
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        build(&tvm, &mut chunk).unwrap();
+        let expected = chunk.bytecode.to_vec();
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+        assert_optimized(build, &expected);
+    }
+
+    #[test]
+    fn test_not_jump_if_false() {
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), TRUE, 0)?;
+                chunk.write_instruction::<0>(tvm, line(2), NOT, 0)?;
+                chunk.write_instruction::<2>(
+                    tvm,
+                    line(2),
+                    JUMP_IF_FALSE,
+                    3,
+                )?;
+                chunk.write_instruction::<0>(tvm, line(3), POP, 0)?;
+                chunk.write_instruction::<0>(tvm, line(3), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(3), RETURN, 0)?;
+                chunk.write_instruction::<0>(tvm, line(4), POP, 0)?;
+                chunk.write_instruction::<0>(tvm, line(4), FALSE, 0)?;
+                chunk.write_instruction::<0>(tvm, line(4), RETURN, 0)?;
+                Ok(())
+            },
+            &[
+                TRUE.into(),
+                JUMP_IF_TRUE.into(),
+                3,
+                0,
+                POP.into(),
+                NIL.into(),
+                RETURN.into(),
+                POP.into(),
+                FALSE.into(),
+                RETURN.into(),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_build_unpack_tuple() {
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, line(1), TRUE, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), BUILD_TUPLE, 2)?;
+                chunk.write_instruction::<1>(tvm, line(1), UNPACK_TUPLE, 2)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                Ok(())
+            },
+            &[NIL.into(), TRUE.into(), RETURN.into()],
+        );
+        // Arity mismatch is left to the runtime error
+        assert_optimized(
+            |tvm, chunk| {
+                chunk.write_instruction::<0>(tvm, line(1), NIL, 0)?;
+                chunk.write_instruction::<1>(tvm, line(1), BUILD_TUPLE, 1)?;
+                chunk.write_instruction::<1>(tvm, line(1), UNPACK_TUPLE, 2)?;
+                chunk.write_instruction::<0>(tvm, line(1), RETURN, 0)?;
+                Ok(())
+            },
+            &[
+                NIL.into(),
+                BUILD_TUPLE.into(),
+                1,
+                UNPACK_TUPLE.into(),
+                2,
+                RETURN.into(),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        chunk.write_instruction::<0>(&tvm, line(1), NIL, 0).unwrap();
+        chunk.write_instruction::<0>(&tvm, line(1), NIL, 0).unwrap();
+        chunk
+            .write_instruction::<1>(&tvm, line(2), SET_LOCAL, 1)
+            .unwrap();
+        chunk.write_instruction::<0>(&tvm, line(2), POP, 0).unwrap();
+        chunk
+            .write_instruction::<1>(&tvm, line(3), GET_LOCAL, 1)
+            .unwrap();
+        chunk
+            .write_instruction::<0>(&tvm, line(4), RETURN, 0)
+            .unwrap();
+        optimize(&tvm, &mut chunk);
+        assert_eq!(chunk.bytecode.len(), 5);
+        assert_eq!(chunk.get_line(0), 1);
+        assert_eq!(chunk.get_line(2), 2);
+        assert_eq!(chunk.get_line(4), 4);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+    }
+}
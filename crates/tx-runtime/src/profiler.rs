@@ -0,0 +1,194 @@
+use std::{cmp::Reverse, collections::HashMap, fmt, io, time::Duration};
+
+/// Frame of a sampled call stack: the function and the line being executed
+/// in it, the call site for the frames below the top one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfileFrame<'a> {
+    pub function: &'a str,
+    pub line: u32,
+}
+
+/// Time spent in a function or line, including (inclusive) or excluding
+/// (exclusive) the functions it calls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProfileTimes {
+    pub inclusive: Duration,
+    pub exclusive: Duration,
+}
+
+/// Aggregates sampled call stacks per function, per source line and per
+/// distinct stack, for `--profile`.
+pub struct Profiler {
+    functions: HashMap<String, ProfileTimes>,
+    lines: HashMap<(String, u32), ProfileTimes>,
+    // Function names from the outermost frame, separated by ';'
+    folded: HashMap<String, Duration>,
+}
+
+impl Profiler {
+    const MAX_LINES_DISPLAYED: usize = 20;
+
+    pub fn new() -> Self {
+        Self {
+            functions: HashMap::new(),
+            lines: HashMap::new(),
+            folded: HashMap::new(),
+        }
+    }
+
+    /// To be called by the sampling hook with the frame stack, outermost
+    /// frame first, and the time elapsed since the previous sample.
+    /// Recursive functions and lines count once per sample for inclusive
+    /// time.
+    pub fn record(&mut self, stack: &[ProfileFrame], elapsed: Duration) {
+        let Some((top, callers)) = stack.split_last() else {
+            return;
+        };
+        for (idx, frame) in stack.iter().enumerate() {
+            let outer = &stack[..idx];
+            if !outer.iter().any(|other| other.function == frame.function) {
+                let times = self
+                    .functions
+                    .entry(frame.function.to_owned())
+                    .or_default();
+                times.inclusive += elapsed;
+            }
+            if !outer.contains(frame) {
+                let times = self
+                    .lines
+                    .entry((frame.function.to_owned(), frame.line))
+                    .or_default();
+                times.inclusive += elapsed;
+            }
+        }
+        self.functions.get_mut(top.function).unwrap().exclusive += elapsed;
+        self.lines
+            .get_mut(&(top.function.to_owned(), top.line))
+            .unwrap()
+            .exclusive += elapsed;
+        let mut folded = String::new();
+        for frame in callers {
+            folded.push_str(frame.function);
+            folded.push(';');
+        }
+        folded.push_str(top.function);
+        *self.folded.entry(folded).or_default() += elapsed;
+    }
+
+    pub fn get_function_times(&self, function: &str) -> ProfileTimes {
+        self.functions.get(function).copied().unwrap_or_default()
+    }
+
+    pub fn get_line_times(&self, function: &str, line: u32) -> ProfileTimes {
+        self.lines
+            .get(&(function.to_owned(), line))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Write one line per distinct stack with its time in microseconds,
+    /// the format expected by flamegraph tools.
+    pub fn write_folded(&self, out: &mut impl io::Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.folded.iter().collect();
+        stacks.sort_unstable();
+        for (stack, time) in stacks {
+            writeln!(out, "{stack} {}", time.as_micros())?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_unstable_by_key(|(_, times)| Reverse(times.inclusive));
+        writeln!(f, "== functions (inclusive ms, exclusive ms) ==")?;
+        for (name, times) in functions {
+            writeln!(
+                f,
+                "{name:<30} {:>12.3} {:>12.3}",
+                ms(times.inclusive),
+                ms(times.exclusive)
+            )?;
+        }
+        let mut lines: Vec<_> = self.lines.iter().collect();
+        lines.sort_unstable_by_key(|(_, times)| Reverse(times.exclusive));
+        writeln!(f, "== hottest lines (inclusive ms, exclusive ms) ==")?;
+        for ((name, line), times) in
+            lines.into_iter().take(Self::MAX_LINES_DISPLAYED)
+        {
+            let location = format!("{name}:{line}");
+            writeln!(
+                f,
+                "{location:<30} {:>12.3} {:>12.3}",
+                ms(times.inclusive),
+                ms(times.exclusive)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let frame = |function, line| ProfileFrame { function, line };
+        let ms = Duration::from_millis;
+        let mut profiler = Profiler::new();
+        profiler.record(&[frame("main", 1)], ms(1));
+        profiler.record(&[frame("main", 2), frame("fib", 5)], ms(2));
+        profiler.record(
+            &[frame("main", 2), frame("fib", 6), frame("fib", 5)],
+            ms(4),
+        );
+        profiler.record(&[], ms(8));
+        assert_eq!(
+            profiler.get_function_times("main"),
+            ProfileTimes {
+                inclusive: ms(7),
+                exclusive: ms(1),
+            }
+        );
+        assert_eq!(
+            profiler.get_function_times("fib"),
+            ProfileTimes {
+                inclusive: ms(6),
+                exclusive: ms(6),
+            }
+        );
+        assert_eq!(
+            profiler.get_line_times("main", 2),
+            ProfileTimes {
+                inclusive: ms(6),
+                exclusive: ms(0),
+            }
+        );
+        assert_eq!(
+            profiler.get_line_times("fib", 5),
+            ProfileTimes {
+                inclusive: ms(6),
+                exclusive: ms(6),
+            }
+        );
+        assert_eq!(profiler.get_line_times("fib", 6).inclusive, ms(4));
+        assert_eq!(profiler.get_function_times("other"), Default::default());
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "main 1000\nmain;fib 2000\nmain;fib;fib 4000\n"
+        );
+        let report = profiler.to_string();
+        assert!(report.lines().nth(1).unwrap().starts_with("main "));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{
+    allocator::AllocCategory,
+    hash_map::HashMap,
+    value::Value,
+    vm::{RuntimeError, VmAlloc, VM},
+};
+
+/// Set of `Value`s, sharing the `Map` allocation category. Members compare
+/// with `==` like map keys and the same values are rejected as unhashable.
+/// Iteration order is unspecified.
+pub struct TxSet<'a> {
+    members: HashMap<'a, Value, bool, VmAlloc>,
+    // The HashMap len also counts tombstones
+    len: usize,
+}
+
+impl<'a> TxSet<'a> {
+    pub fn new(tvm: &'a VM) -> Self {
+        Self {
+            members: HashMap::new(&tvm.allocator),
+            len: 0,
+        }
+    }
+
+    /// Set literal or `set()` of a sequence, duplicates are dropped.
+    pub fn from_values(
+        tvm: &'a VM,
+        values: &[Value],
+    ) -> Result<Self, RuntimeError> {
+        let mut set = Self::new(tvm);
+        if let Err(err) = set.add_all(tvm, values.iter().copied()) {
+            unsafe {
+                set.destroy(tvm);
+            }
+            return Err(err);
+        }
+        Ok(set)
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::Map, || {
+            self.members.destroy(&tvm.allocator);
+        });
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add a value, returns true if it was not already in the set.
+    pub fn add(
+        &mut self,
+        tvm: &VM,
+        value: Value,
+    ) -> Result<bool, RuntimeError> {
+        check_hashable(value)?;
+        let is_new =
+            tvm.allocator.with_category(AllocCategory::Map, || unsafe {
+                self.members.try_set(&tvm.allocator, value, true)
+            })?;
+        if is_new {
+            self.len += 1;
+        }
+        Ok(is_new)
+    }
+
+    /// Remove a value, returns true if it was in the set.
+    pub fn remove(&mut self, value: Value) -> Result<bool, RuntimeError> {
+        check_hashable(value)?;
+        let was_present = self.members.remove(&value);
+        if was_present {
+            self.len -= 1;
+        }
+        Ok(was_present)
+    }
+
+    pub fn contains(&self, value: Value) -> Result<bool, RuntimeError> {
+        check_hashable(value)?;
+        Ok(self.has(value))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Value> + '_ {
+        self.members.iter().map(|(&value, _)| value)
+    }
+
+    pub fn union(
+        &self,
+        tvm: &'a VM,
+        other: &TxSet,
+    ) -> Result<Self, RuntimeError> {
+        let mut result = self.filtered(tvm, |_| true)?;
+        if let Err(err) = result.add_all(tvm, other.iter()) {
+            unsafe {
+                result.destroy(tvm);
+            }
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    pub fn intersection(
+        &self,
+        tvm: &'a VM,
+        other: &TxSet,
+    ) -> Result<Self, RuntimeError> {
+        self.filtered(tvm, |value| other.has(value))
+    }
+
+    pub fn difference(
+        &self,
+        tvm: &'a VM,
+        other: &TxSet,
+    ) -> Result<Self, RuntimeError> {
+        self.filtered(tvm, |value| !other.has(value))
+    }
+
+    pub fn is_subset(&self, other: &TxSet) -> bool {
+        self.len <= other.len && self.iter().all(|value| other.has(value))
+    }
+
+    fn has(&self, value: Value) -> bool {
+        self.members.get(&value).is_some()
+    }
+
+    fn add_all(
+        &mut self,
+        tvm: &VM,
+        values: impl Iterator<Item = Value>,
+    ) -> Result<(), RuntimeError> {
+        for value in values {
+            self.add(tvm, value)?;
+        }
+        Ok(())
+    }
+
+    fn filtered(
+        &self,
+        tvm: &'a VM,
+        keep: impl Fn(Value) -> bool,
+    ) -> Result<Self, RuntimeError> {
+        let mut result = Self::new(tvm);
+        if let Err(err) =
+            result.add_all(tvm, self.iter().filter(|&value| keep(value)))
+        {
+            unsafe {
+                result.destroy(tvm);
+            }
+            return Err(err);
+        }
+        Ok(result)
+    }
+}
+
+fn check_hashable(value: Value) -> Result<(), RuntimeError> {
+    if value.is_hashable() {
+        Ok(())
+    } else {
+        Err(RuntimeError::UnhashableKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TxFloat;
+
+    fn sorted(set: &TxSet) -> Vec<Value> {
+        let mut values: Vec<_> = set.iter().collect();
+        values.sort_by_key(|value| match value {
+            Value::Int(i) => *i,
+            _ => unreachable!(),
+        });
+        values
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let tvm = VM::new();
+        let ints = |values: &[_]| {
+            let values: Vec<_> =
+                values.iter().map(|&i| Value::Int(i)).collect();
+            TxSet::from_values(&tvm, &values).unwrap()
+        };
+        let mut a = ints(&[1, 2, 3, 3, 2]);
+        let mut b = ints(&[2, 3, 4]);
+        assert_eq!(a.len(), 3);
+        // The member stays an Int
+        assert_eq!(a.add(&tvm, Value::Float(1.0)), Ok(false));
+        assert_eq!(a.contains(Value::Float(3.0)), Ok(true));
+        assert_eq!(
+            a.add(&tvm, Value::Float(TxFloat::NAN)),
+            Err(RuntimeError::UnhashableKey)
+        );
+        let mut union = a.union(&tvm, &b).unwrap();
+        let mut intersection = a.intersection(&tvm, &b).unwrap();
+        let mut difference = a.difference(&tvm, &b).unwrap();
+        assert_eq!(sorted(&union), [1, 2, 3, 4].map(Value::Int));
+        assert_eq!(sorted(&intersection), [2, 3].map(Value::Int));
+        assert_eq!(sorted(&difference), [Value::Int(1)]);
+        assert!(intersection.is_subset(&a));
+        assert!(intersection.is_subset(&b));
+        assert!(!a.is_subset(&b));
+        assert_eq!(b.remove(Value::Int(4)), Ok(true));
+        assert_eq!(b.remove(Value::Int(4)), Ok(false));
+        assert!(b.is_subset(&a));
+        assert_eq!(b.len(), 2);
+        unsafe {
+            for set in [
+                &mut a,
+                &mut b,
+                &mut union,
+                &mut intersection,
+                &mut difference,
+            ] {
+                set.destroy(&tvm);
+            }
+        }
+        assert!(
+            TxSet::from_values(&tvm, &[Value::Float(TxFloat::NAN)]).is_err()
+        );
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    allocator::AllocCategory,
+    dyn_array::DynArray,
+    hash_map::HashMap,
+    list::get_index,
+    object::{free_object, new_object, Object},
+    value::Value,
+    vm::{RuntimeError, VmAlloc, VM},
+};
+
+/// Immutable UTF-8 string. The hash is computed once at creation.
+///
+/// Indexing and iteration are by Unicode scalar value and give Chars.
+pub struct TxString<'a> {
+    bytes: DynArray<'a, u8, VmAlloc>,
+    hash: u32,
+    char_count: usize,
+}
+
+impl<'a> TxString<'a> {
+    pub fn new(tvm: &'a VM, string: &str) -> Result<Self, RuntimeError> {
+        Self::from_parts(tvm, &[string])
+    }
+
+    /// Concatenation for `+`.
+    pub fn concat(
+        tvm: &'a VM,
+        lhs: &TxString,
+        rhs: &TxString,
+    ) -> Result<Self, RuntimeError> {
+        Self::from_parts(tvm, &[lhs.as_str(), rhs.as_str()])
+    }
+
+    /// Build a string from its parts with a single allocation, used for
+    /// the parts of an interpolated string by BUILD_STRING.
+    pub fn from_parts(
+        tvm: &'a VM,
+        parts: &[&str],
+    ) -> Result<Self, RuntimeError> {
+        let len = parts.iter().map(|part| part.len()).sum();
+        let mut bytes = DynArray::new(&tvm.allocator);
+        tvm.allocator
+            .with_category(AllocCategory::String, || unsafe {
+                bytes.try_reserve(&tvm.allocator, len)?;
+                for part in parts {
+                    bytes.try_extend_from_slice(
+                        &tvm.allocator,
+                        part.as_bytes(),
+                    )?;
+                }
+                Ok::<_, RuntimeError>(())
+            })?;
+        let hash = hash_bytes(&bytes);
+        let char_count = parts.iter().map(|part| part.chars().count()).sum();
+        Ok(Self {
+            bytes,
+            hash,
+            char_count,
+        })
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::String, || {
+            self.bytes.destroy(&tvm.allocator);
+        });
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ever built from complete `&str`s
+        unsafe { std::str::from_utf8_unchecked(&self.bytes) }
+    }
+
+    pub fn get_hash(&self) -> u32 {
+        self.hash
+    }
+
+    /// Number of Chars, not bytes.
+    pub fn len(&self) -> usize {
+        self.char_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.char_count == 0
+    }
+
+    /// `string[index]`, with the same index rules as lists.
+    pub fn char_at(&self, index: &Value) -> Result<Value, RuntimeError> {
+        let idx = get_index(*index, self.char_count, false)?;
+        let c = if self.char_count == self.bytes.len() {
+            // ASCII only, no need to decode
+            char::from(self.bytes[idx])
+        } else {
+            self.as_str().chars().nth(idx).unwrap()
+        };
+        Ok(Value::Char(c))
+    }
+
+    /// Iterate over the Chars, for `for c in string`.
+    pub fn chars(&self) -> impl Iterator<Item = Value> + '_ {
+        self.as_str().chars().map(Value::Char)
+    }
+}
+
+impl PartialEq for TxString<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for TxString<'_> {}
+
+/// Table of interned strings: interning equal strings gives the same
+/// object, for identifiers and literals. Interned objects are owned by the
+/// table and freed with it, never with `free_object`.
+pub struct StringInterner<'a> {
+    strings: HashMap<'a, Value, bool, VmAlloc>,
+}
+
+impl<'a> StringInterner<'a> {
+    pub fn new(tvm: &'a VM) -> Self {
+        Self {
+            strings: HashMap::new(&tvm.allocator),
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        for (&value, _) in self.strings.iter() {
+            if let Value::Object(obj) = value {
+                free_object(tvm, obj);
+            }
+        }
+        tvm.allocator.with_category(AllocCategory::String, || {
+            self.strings.destroy(&tvm.allocator);
+        });
+    }
+
+    /// The interned string equal to `string`, created on first use.
+    pub fn intern(
+        &mut self,
+        tvm: &'a VM,
+        string: &str,
+    ) -> Result<Value, RuntimeError> {
+        let hash = hash_bytes(string.as_bytes()) as usize;
+        let interned = self.strings.find_key(hash, |key| match key {
+            Value::Object(obj) => match obj.get() {
+                Object::String(other) => other.as_str() == string,
+                _ => false,
+            },
+            _ => false,
+        });
+        if let Some(&value) = interned {
+            return Ok(value);
+        }
+        let value =
+            new_object(tvm, Object::String(TxString::new(tvm, string)?))?;
+        let result = tvm
+            .allocator
+            .with_category(AllocCategory::String, || unsafe {
+                self.strings.try_set(&tvm.allocator, value, true)
+            });
+        if let Err(err) = result {
+            if let Value::Object(obj) = value {
+                unsafe {
+                    free_object(tvm, obj);
+                }
+            }
+            return Err(err.into());
+        }
+        Ok(value)
+    }
+}
+
+// FNV-1a
+fn hash_bytes(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(2_166_136_261, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(16_777_619)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concat() {
+        let tvm = VM::new();
+        let mut hello = TxString::new(&tvm, "Hello, ").unwrap();
+        let mut name = TxString::new(&tvm, "wörld").unwrap();
+        let mut greeting = TxString::concat(&tvm, &hello, &name).unwrap();
+        let mut built =
+            TxString::from_parts(&tvm, &["Hello", ", ", "wörld"]).unwrap();
+        assert_eq!(greeting.as_str(), "Hello, wörld");
+        assert!(greeting == built);
+        assert_eq!(greeting.get_hash(), built.get_hash());
+        assert!(greeting != hello);
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::String as usize].count, 4);
+        assert_eq!(stats.categories[AllocCategory::Other as usize].count, 0);
+        unsafe {
+            for string in [&mut hello, &mut name, &mut greeting, &mut built] {
+                string.destroy(&tvm);
+            }
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_intern() {
+        let tvm = VM::new();
+        let mut interner = StringInterner::new(&tvm);
+        let get_obj = |value| match value {
+            Ok(Value::Object(obj)) => obj,
+            _ => panic!("expected an object"),
+        };
+        let hello = get_obj(interner.intern(&tvm, "hello"));
+        let world = get_obj(interner.intern(&tvm, "w\u{f6}rld"));
+        assert_ne!(hello, world);
+        for _ in 0..20 {
+            assert_eq!(get_obj(interner.intern(&tvm, "hello")), hello);
+        }
+        assert_eq!(get_obj(interner.intern(&tvm, "w\u{f6}rld")), world);
+        // Two objects with their bytes, and the table
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::String as usize].count, 5);
+        unsafe {
+            interner.destroy(&tvm);
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+
+    #[test]
+    fn test_chars() {
+        let tvm = VM::new();
+        let mut ascii = TxString::new(&tvm, "abc").unwrap();
+        let mut mixed = TxString::new(&tvm, "a\u{e9}\u{1F600}z").unwrap();
+        let char_at = |string: &TxString, i| string.char_at(&Value::Int(i));
+        assert_eq!(char_at(&ascii, -1), Ok(Value::Char('c')));
+        assert_eq!(mixed.len(), 4);
+        assert_eq!(char_at(&mixed, 1), Ok(Value::Char('\u{e9}')));
+        assert_eq!(char_at(&mixed, 2), Ok(Value::Char('\u{1F600}')));
+        assert_eq!(char_at(&mixed, -1), Ok(Value::Char('z')));
+        assert_eq!(char_at(&mixed, 4), Err(RuntimeError::IndexOutOfRange));
+        assert_eq!(
+            mixed.char_at(&Value::Char('a')),
+            Err(RuntimeError::IndexMustBeInteger)
+        );
+        let chars: Vec<_> = mixed.chars().collect();
+        assert_eq!(chars, ['a', '\u{e9}', '\u{1F600}', 'z'].map(Value::Char));
+        unsafe {
+            ascii.destroy(&tvm);
+            mixed.destroy(&tvm);
+        }
+    }
+}
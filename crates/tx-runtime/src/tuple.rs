@@ -0,0 +1,120 @@
+use crate::{
+    allocator::AllocCategory,
+    dyn_array::DynArray,
+    hash_map::HashMapKey,
+    list::get_index,
+    value::Value,
+    vm::{RuntimeError, VmAlloc, VM},
+};
+
+/// Immutable tuple. Tuples compare element-wise with `==` and are hashable
+/// when all their elements are, the hash is computed once at creation.
+pub struct TxTuple<'a> {
+    items: DynArray<'a, Value, VmAlloc>,
+    hash: Option<usize>,
+}
+
+impl<'a> TxTuple<'a> {
+    pub fn new(tvm: &'a VM, values: &[Value]) -> Result<Self, RuntimeError> {
+        let mut items = DynArray::new(&tvm.allocator);
+        tvm.allocator
+            .with_category(AllocCategory::Tuple, || unsafe {
+                items.try_extend_from_slice(&tvm.allocator, values)
+            })?;
+        let hash = values.iter().try_fold(values.len(), |hash, value| {
+            value
+                .is_hashable()
+                .then(|| hash.rotate_left(5) ^ value.get_hash())
+        });
+        Ok(Self { items, hash })
+    }
+
+    pub unsafe fn destroy(&mut self, tvm: &VM) {
+        tvm.allocator.with_category(AllocCategory::Tuple, || {
+            self.items.destroy(&tvm.allocator);
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn as_slice(&self) -> &[Value] {
+        &self.items
+    }
+
+    pub fn get(&self, index: Value) -> Result<Value, RuntimeError> {
+        let idx = get_index(index, self.len(), false)?;
+        Ok(self.items[idx])
+    }
+
+    pub fn is_hashable(&self) -> bool {
+        self.hash.is_some()
+    }
+
+    /// Hash for use as a map key, an error if an element is unhashable.
+    pub fn get_hash(&self) -> Result<usize, RuntimeError> {
+        self.hash.ok_or(RuntimeError::UnhashableKey)
+    }
+
+    /// Push the elements for UNPACK_TUPLE, that checks the arity.
+    pub fn unpack(&self, count: usize) -> Result<&[Value], RuntimeError> {
+        if count == self.len() {
+            Ok(&self.items)
+        } else {
+            Err(RuntimeError::TupleArityMismatch {
+                expected: count,
+                found: self.len(),
+            })
+        }
+    }
+}
+
+impl PartialEq for TxTuple<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.items[..] == other.items[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TxFloat;
+
+    #[test]
+    fn test_tuple() {
+        let tvm = VM::new();
+        let mut tuple = |values: &[_]| TxTuple::new(&tvm, values).unwrap();
+        let mut a = tuple(&[Value::Int(1), Value::Char('x')]);
+        let mut b = tuple(&[Value::Float(1.0), Value::Char('x')]);
+        let mut c = tuple(&[Value::Float(TxFloat::NAN)]);
+        let mut empty = tuple(&[]);
+        assert!(a == b);
+        assert_eq!(a.get_hash(), b.get_hash());
+        assert!(a != c);
+        assert_eq!(c.get_hash(), Err(RuntimeError::UnhashableKey));
+        assert!(empty.is_hashable());
+        assert_eq!(a.get(Value::Int(-1)), Ok(Value::Char('x')));
+        assert_eq!(a.get(Value::Int(2)), Err(RuntimeError::IndexOutOfRange));
+        assert_eq!(b.unpack(2).map(<[_]>::len), Ok(2));
+        assert_eq!(
+            b.unpack(3),
+            Err(RuntimeError::TupleArityMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        let stats = tvm.alloc_stats();
+        assert_eq!(stats.categories[AllocCategory::Tuple as usize].count, 3);
+        unsafe {
+            for tuple in [&mut a, &mut b, &mut c, &mut empty] {
+                tuple.destroy(&tvm);
+            }
+        }
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+    }
+}
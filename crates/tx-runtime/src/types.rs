@@ -1,4 +1,3 @@
-
 #[cfg(not(feature = "tx32"))]
 pub type TxInt = i64;
 #[cfg(not(feature = "tx32"))]
@@ -10,4 +9,3 @@ pub type TxInt = i32;
 pub type TxFloat = f32;
 
 // pub type DynArray<T> = Vec<T>;
-
@@ -1,6 +1,12 @@
-use crate::types::{TxInt, TxFloat};
+use std::cmp::Ordering;
 
-#[derive(Clone, Copy, PartialEq)]
+use crate::{
+    hash_map::{HashMapKey, HashMapValue},
+    object::{ObjRef, Object},
+    types::{TxFloat, TxInt},
+};
+
+#[derive(Clone, Copy, Debug)]
 pub enum Value {
     None,
     Nil,
@@ -8,5 +14,175 @@ pub enum Value {
     Int(TxInt),
     Float(TxFloat),
     Char(char),
-    Object(usize),
+    Object(ObjRef),
+}
+
+impl Value {
+    pub const fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Bitwise identity of the value. Unlike `==` it tells `0.0` and `-0.0`
+    /// apart, matches a NaN with itself and never mixes `Int` and `Float`.
+    // Casts are not no-ops with the tx32 feature
+    #[allow(clippy::unnecessary_cast)]
+    pub fn to_bits(self) -> ValueBits {
+        let (tag, bits) = match self {
+            Value::None => (0, 0),
+            Value::Nil => (1, 0),
+            Value::Bool(b) => (2, b as u64),
+            Value::Int(i) => (3, i as u64),
+            Value::Float(f) => (4, f.to_bits() as u64),
+            Value::Char(c) => (5, c as u64),
+            Value::Object(o) => (6, o.addr() as u64),
+        };
+        ValueBits { tag, bits }
+    }
+
+    /// Whether the value can be a map key. NaN is not, as it is not equal
+    /// to itself and could never be found again. Of the objects, only
+    /// strings and tuples of hashable values are, as they are immutable.
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Value::None => false,
+            Value::Float(f) => !f.is_nan(),
+            Value::Object(o) => match o.get() {
+                Object::String(_) => true,
+                Object::Tuple(tuple) => tuple.is_hashable(),
+                _ => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+/// Numbers compare by mathematical value, so `1 == 1.0` is true and
+/// Int/Float comparisons agree with equality. Strings and tuples compare
+/// by content, other objects by identity. Other values are only equal to
+/// values of the same type.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (Value::None, Value::None) | (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(i), Value::Float(f)) => {
+                compare_int_float(i, f) == Some(Ordering::Equal)
+            }
+            (Value::Float(f), Value::Int(i)) => {
+                compare_int_float(i, f) == Some(Ordering::Equal)
+            }
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => {
+                a == b
+                    || match (a.get(), b.get()) {
+                        (Object::String(a), Object::String(b)) => a == b,
+                        (Object::Tuple(a), Object::Tuple(b)) => a == b,
+                        _ => false,
+                    }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Exact comparison of an Int with a Float, `None` if the Float is NaN.
+/// Converting the Int to Float would round large values and make distinct
+/// Ints compare equal to the same Float.
+pub fn compare_int_float(i: TxInt, f: TxFloat) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    // TxInt::MAX rounds up to 2^63 (2^31 with tx32), MIN is exact
+    if f >= TxInt::MAX as TxFloat {
+        return Some(Ordering::Less);
+    }
+    if f < TxInt::MIN as TxFloat {
+        return Some(Ordering::Greater);
+    }
+    let truncated = f.trunc();
+    match i.cmp(&(truncated as TxInt)) {
+        Ordering::Equal => (0.0).partial_cmp(&(f - truncated)),
+        ordering => Some(ordering),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueBits {
+    tag: u8,
+    bits: u64,
+}
+
+impl HashMapKey<ValueBits> for ValueBits {
+    // A tag `to_bits` never produces, so that any value can be interned
+    const EMPTY_KEY: ValueBits = ValueBits {
+        tag: u8::MAX,
+        bits: 0,
+    };
+
+    fn get_hash(&self) -> usize {
+        // Fibonacci hashing, folded so the low bits depend on all the input
+        let hash = (self.bits ^ u64::from(self.tag).rotate_right(8))
+            .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (hash ^ (hash >> 32)) as usize
+    }
+}
+
+/// Map keys compare with `==`, so integral Floats hash like the equal Int
+/// and strings and tuples hash their content.
+impl HashMapKey<Value> for Value {
+    const EMPTY_KEY: Value = Value::None;
+
+    fn get_hash(&self) -> usize {
+        match *self {
+            Value::Float(f) if (f as TxInt) as TxFloat == f => {
+                Value::Int(f as TxInt).to_bits().get_hash()
+            }
+            Value::Object(o) => match o.get() {
+                Object::String(string) => string.get_hash() as usize,
+                // Unhashable tuples are rejected before hashing
+                Object::Tuple(tuple) => tuple.get_hash().unwrap_or(0),
+                _ => self.to_bits().get_hash(),
+            },
+            value => value.to_bits().get_hash(),
+        }
+    }
+}
+
+impl HashMapValue<Value> for Value {
+    const EMPTY_VALUE: Value = Value::Nil;
+    const TOMBSTONE_VALUE: Value = Value::Bool(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric_equality() {
+        assert_eq!(Value::Int(1), Value::Float(1.0));
+        assert_eq!(Value::Float(-0.0), Value::Int(0));
+        assert_ne!(Value::Int(1), Value::Float(1.5));
+        assert_ne!(Value::Int(1), Value::Bool(true));
+        assert_ne!(Value::Float(TxFloat::NAN), Value::Float(TxFloat::NAN));
+        assert_ne!(Value::Int(0).to_bits(), Value::Float(0.0).to_bits());
+        assert_eq!(Value::Int(0).get_hash(), Value::Float(-0.0).get_hash());
+        assert_eq!(Value::Int(-3).get_hash(), Value::Float(-3.0).get_hash());
+        assert!(!Value::Float(TxFloat::NAN).is_hashable());
+        let max = TxInt::MAX as TxFloat;
+        assert_eq!(compare_int_float(TxInt::MAX, max), Some(Ordering::Less));
+        assert_eq!(
+            compare_int_float(TxInt::MIN, TxInt::MIN as TxFloat),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(compare_int_float(-1, -1.5), Some(Ordering::Greater));
+        assert_eq!(compare_int_float(2, 1.5), Some(Ordering::Greater));
+        assert_eq!(compare_int_float(1, 1.5), Some(Ordering::Less));
+        assert_eq!(compare_int_float(0, TxFloat::NAN), None);
+        assert_eq!(
+            compare_int_float(0, TxFloat::NEG_INFINITY),
+            Some(Ordering::Greater)
+        );
+    }
 }
@@ -0,0 +1,451 @@
+use std::fmt;
+
+use crate::{
+    chunk::{read_multibyte_operand, Chunk},
+    opcodes::*,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    InvalidOpCode {
+        offset: usize,
+        byte: u8,
+    },
+    TruncatedInstruction {
+        offset: usize,
+    },
+    ConstantOutOfRange {
+        offset: usize,
+        index: usize,
+    },
+    LocalOutOfRange {
+        offset: usize,
+        slot: usize,
+    },
+    InvalidJumpTarget {
+        offset: usize,
+        target: isize,
+    },
+    StackUnderflow {
+        offset: usize,
+    },
+    InconsistentStackDepth {
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    MissingReturn {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::InvalidOpCode { offset, byte } => {
+                write!(f, "{offset:04}: invalid opcode {byte:#04x}")
+            }
+            Self::TruncatedInstruction { offset } => {
+                write!(f, "{offset:04}: truncated instruction")
+            }
+            Self::ConstantOutOfRange { offset, index } => {
+                write!(f, "{offset:04}: constant {index} out of range")
+            }
+            Self::LocalOutOfRange { offset, slot } => {
+                write!(f, "{offset:04}: local slot {slot} out of range")
+            }
+            Self::InvalidJumpTarget { offset, target } => {
+                write!(f, "{offset:04}: invalid jump target {target}")
+            }
+            Self::StackUnderflow { offset } => {
+                write!(f, "{offset:04}: stack underflow")
+            }
+            Self::InconsistentStackDepth {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{offset:04}: inconsistent stack depth \
+                 (expected {expected}, found {found})"
+            ),
+            Self::MissingReturn { offset } => {
+                write!(f, "{offset:04}: execution runs past end of bytecode")
+            }
+        }
+    }
+}
+
+/// Check that the bytecode of a chunk can be executed without the VM
+/// reading out of bounds: valid opcodes and operands, jumps landing on
+/// instruction boundaries and a stack depth that never goes negative and
+/// is the same along all paths reaching an instruction.
+///
+/// Returns the maximum stack depth reached, relative to the frame base.
+pub fn verify(chunk: &Chunk) -> Result<usize, VerifyError> {
+    let code = &chunk.bytecode[..];
+    let is_start = decode_boundaries(code)?;
+    let mut depths: Vec<Option<usize>> = vec![None; code.len()];
+    let mut worklist = vec![(0, 0)];
+    let mut max_depth = 0;
+    while let Some((offset, depth)) = worklist.pop() {
+        if offset == code.len() {
+            return Err(VerifyError::MissingReturn { offset });
+        }
+        match depths[offset] {
+            Some(expected) if expected == depth => continue,
+            Some(expected) => {
+                return Err(VerifyError::InconsistentStackDepth {
+                    offset,
+                    expected,
+                    found: depth,
+                })
+            }
+            None => depths[offset] = Some(depth),
+        }
+        let opc = OpCode::try_from(code[offset]).unwrap();
+        let len = 1 + opc.get_num_operands();
+        let operand = read_multibyte_operand(&code[offset + 1..offset + len]);
+        let next = offset + len;
+        let (required, effect) = match opc {
+            CONSTANT | CONSTANT_LONG | CLOSURE | CLOSURE_LONG | CLASS
+            | CLASS_LONG | TRAIT | TRAIT_LONG => {
+                check_constant(chunk, offset, operand)?;
+                (0, opc.get_stack_effect())
+            }
+            GET_PROPERTY | GET_PROPERTY_LONG => {
+                check_constant(chunk, offset, operand)?;
+                (1, opc.get_stack_effect())
+            }
+            METHOD | METHOD_LONG | SET_PROPERTY | SET_PROPERTY_LONG
+            | GET_SUPER | GET_SUPER_LONG => {
+                check_constant(chunk, offset, operand)?;
+                (2, opc.get_stack_effect())
+            }
+            INVOKE | INVOKE_LONG => {
+                let (name, arg_count) = split_invoke_operand(opc, operand);
+                check_constant(chunk, offset, name)?;
+                // Like CALL, with the receiver in the callee slot
+                (arg_count + 1, -(arg_count as isize))
+            }
+            SUPER_INVOKE | SUPER_INVOKE_LONG => {
+                let (name, arg_count) = split_invoke_operand(opc, operand);
+                check_constant(chunk, offset, name)?;
+                // Like INVOKE, with the superclass above the arguments
+                (arg_count + 2, -(arg_count as isize) - 1)
+            }
+            GET_LOCAL | GET_LOCAL_LONG | SET_LOCAL | SET_LOCAL_LONG => {
+                if operand >= depth {
+                    return Err(VerifyError::LocalOutOfRange {
+                        offset,
+                        slot: operand,
+                    });
+                }
+                (1, opc.get_stack_effect())
+            }
+            SET_GLOBAL | SET_GLOBAL_LONG | SET_UPVALUE | SET_UPVALUE_LONG
+            | NOT | NEGATE | BIT_NOT | JUMP_IF_FALSE | JUMP_IF_TRUE
+            | RETURN => (1, opc.get_stack_effect()),
+            EQUAL | NOT_EQUAL | GREATER | GREATER_EQUAL | LESS
+            | LESS_EQUAL | ADD | SUBSTRACT | MULTIPLY | DIVIDE
+            | INT_DIVIDE | MODULO | POWER | BIT_AND | BIT_OR | BIT_XOR
+            | SHIFT_LEFT | SHIFT_RIGHT | INHERIT | IS | IMPLEMENT
+            | IMPLEMENTS => (2, opc.get_stack_effect()),
+            // The callee and its arguments are replaced by the result
+            CALL => (operand + 1, -(operand as isize)),
+            END_SCOPE | END_SCOPE_LONG => (operand, -(operand as isize)),
+            BUILD_STRING | BUILD_LIST | BUILD_LIST_LONG | BUILD_SET
+            | BUILD_SET_LONG | BUILD_TUPLE => (operand, 1 - operand as isize),
+            BUILD_MAP | BUILD_MAP_LONG => {
+                (2 * operand, 1 - 2 * operand as isize)
+            }
+            UNPACK_TUPLE => (1, operand as isize - 1),
+            GET_INDEX | DELETE_INDEX | IN => (2, opc.get_stack_effect()),
+            SET_INDEX | GET_SLICE => (3, opc.get_stack_effect()),
+            _ => (0, opc.get_stack_effect()),
+        };
+        let required = required.max((-effect).max(0) as usize);
+        if depth < required {
+            return Err(VerifyError::StackUnderflow { offset });
+        }
+        let new_depth = (depth as isize + effect) as usize;
+        max_depth = max_depth.max(new_depth);
+        let mut jump_to = |target: isize| {
+            if target < 0
+                || target as usize >= code.len()
+                || !is_start[target as usize]
+            {
+                return Err(VerifyError::InvalidJumpTarget { offset, target });
+            }
+            worklist.push((target as usize, new_depth));
+            Ok(())
+        };
+        match opc {
+            JUMP => jump_to(next as isize + operand as isize)?,
+            JUMP_IF_FALSE | JUMP_IF_TRUE => {
+                jump_to(next as isize + operand as isize)?;
+                worklist.push((next, new_depth));
+            }
+            LOOP => jump_to(next as isize - operand as isize)?,
+            RETURN => {}
+            _ => worklist.push((next, new_depth)),
+        }
+    }
+    Ok(max_depth)
+}
+
+fn decode_boundaries(code: &[u8]) -> Result<Vec<bool>, VerifyError> {
+    let mut is_start = vec![false; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        let opc = OpCode::try_from(code[offset])
+            .map_err(|byte| VerifyError::InvalidOpCode { offset, byte })?;
+        is_start[offset] = true;
+        offset += 1 + opc.get_num_operands();
+        if offset > code.len() {
+            return Err(VerifyError::TruncatedInstruction {
+                offset: offset - 1 - opc.get_num_operands(),
+            });
+        }
+    }
+    Ok(is_start)
+}
+
+fn check_constant(
+    chunk: &Chunk,
+    offset: usize,
+    index: usize,
+) -> Result<(), VerifyError> {
+    if index < chunk.constants.len() {
+        Ok(())
+    } else {
+        Err(VerifyError::ConstantOutOfRange { offset, index })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        allocator::AllocCategory,
+        chunk::Span,
+        value::Value,
+        vm::{RuntimeError, VM},
+    };
+
+    const SPAN: Span = Span {
+        file_id: 0,
+        line: 1,
+        column: 1,
+        len: 1,
+    };
+
+    fn with_chunk(
+        build: impl FnOnce(&VM, &mut Chunk) -> Result<(), RuntimeError>,
+    ) -> Result<usize, VerifyError> {
+        let tvm = VM::new();
+        let mut chunk = Chunk::new(&tvm);
+        // Raw pushes must be attributed like the `Chunk` writers
+        tvm.allocator
+            .with_category(AllocCategory::Chunk, || build(&tvm, &mut chunk))
+            .unwrap();
+        let result = verify(&chunk);
+        unsafe {
+            chunk.destroy(&tvm);
+        }
+        result
+    }
+
+    #[test]
+    fn test_valid() {
+        let result = with_chunk(|tvm, chunk| {
+            let idx = chunk.write_constant(tvm, Value::Int(1))?;
+            chunk.write_instruction::<1>(tvm, SPAN, CONSTANT, idx)?;
+            chunk.write_instruction::<1>(tvm, SPAN, GET_LOCAL, 0)?;
+            chunk.write_instruction::<0>(tvm, SPAN, ADD, 0)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(result, Ok(2));
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        let result = with_chunk(|tvm, chunk| unsafe {
+            chunk.bytecode.push(&tvm.allocator, 0xff);
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::InvalidOpCode {
+                offset: 0,
+                byte: 0xff
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let result = with_chunk(|tvm, chunk| unsafe {
+            chunk.bytecode.push(&tvm.allocator, CONSTANT_LONG.into());
+            chunk.bytecode.push(&tvm.allocator, 0);
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::TruncatedInstruction { offset: 0 })
+        );
+    }
+
+    #[test]
+    fn test_constant_out_of_range() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<1>(tvm, SPAN, CONSTANT, 0)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::ConstantOutOfRange {
+                offset: 0,
+                index: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_invoke() {
+        let invoke = |arg_count: usize| {
+            with_chunk(|tvm, chunk| {
+                let name = chunk.write_constant(tvm, Value::Int(0))?;
+                chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+                chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+                let operand = name | (arg_count << 8);
+                chunk.write_instruction::<2>(tvm, SPAN, INVOKE, operand)?;
+                chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+                Ok(())
+            })
+        };
+        assert_eq!(invoke(1), Ok(2));
+        assert_eq!(invoke(2), Err(VerifyError::StackUnderflow { offset: 2 }));
+    }
+
+    #[test]
+    fn test_super_invoke() {
+        let result = with_chunk(|tvm, chunk| {
+            let name = chunk.write_constant(tvm, Value::Int(0))?;
+            for _ in 0..3 {
+                chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+            }
+            let operand = name | (1 << 8);
+            chunk.write_instruction::<2>(tvm, SPAN, SUPER_INVOKE, operand)?;
+            // Only the result is left
+            chunk.write_instruction::<1>(tvm, SPAN, GET_LOCAL, 1)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::LocalOutOfRange { offset: 6, slot: 1 })
+        );
+    }
+
+    #[test]
+    fn test_local_out_of_range() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+            chunk.write_instruction::<1>(tvm, SPAN, GET_LOCAL, 1)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::LocalOutOfRange { offset: 1, slot: 1 })
+        );
+    }
+
+    #[test]
+    fn test_call_consumes_arguments() {
+        let result = with_chunk(|tvm, chunk| {
+            let idx = chunk.write_constant(tvm, Value::Int(1))?;
+            for _ in 0..4 {
+                chunk.write_instruction::<1>(tvm, SPAN, CONSTANT, idx)?;
+            }
+            chunk.write_instruction::<1>(tvm, SPAN, CALL, 3)?;
+            chunk.write_instruction::<1>(tvm, SPAN, GET_LOCAL, 3)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::LocalOutOfRange {
+                offset: 10,
+                slot: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_jump_into_instruction() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<2>(tvm, SPAN, JUMP, 1)?;
+            chunk.write_instruction::<1>(tvm, SPAN, GET_LOCAL, 0)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(
+            result,
+            Err(VerifyError::InvalidJumpTarget {
+                offset: 0,
+                target: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_inconsistent_depth() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<0>(tvm, SPAN, TRUE, 0)?;
+            chunk.write_instruction::<2>(tvm, SPAN, JUMP_IF_FALSE, 1)?;
+            chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert!(matches!(
+            result,
+            Err(VerifyError::InconsistentStackDepth { offset: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+            chunk.write_instruction::<1>(tvm, SPAN, END_SCOPE, 2)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(result, Err(VerifyError::StackUnderflow { offset: 1 }));
+    }
+
+    #[test]
+    fn test_missing_return() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<0>(tvm, SPAN, NIL, 0)?;
+            Ok(())
+        });
+        assert_eq!(result, Err(VerifyError::MissingReturn { offset: 1 }));
+    }
+
+    #[test]
+    fn test_loop() {
+        let result = with_chunk(|tvm, chunk| {
+            chunk.write_instruction::<0>(tvm, SPAN, TRUE, 0)?;
+            chunk.write_instruction::<2>(tvm, SPAN, JUMP_IF_FALSE, 3)?;
+            chunk.write_instruction::<2>(tvm, SPAN, LOOP, 6)?;
+            chunk.write_instruction::<0>(tvm, SPAN, RETURN, 0)?;
+            Ok(())
+        });
+        assert_eq!(result, Ok(1));
+    }
+}
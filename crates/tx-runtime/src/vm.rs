@@ -1,12 +1,252 @@
-use std::alloc::Global;
+use std::{
+    alloc::Global,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use crate::allocator::Alloc;
+#[cfg(feature = "debug-features")]
+use crate::opcode_stats::OpcodeStats;
+use crate::{
+    allocator::{Alloc, AllocStats},
+    dyn_array::TryReserveError,
+};
 
 type InnerAlloc = Global;
 pub type VmAlloc = Alloc<InnerAlloc>;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuntimeError {
+    OutOfFuel,
+    Interrupted,
+    OutOfMemory,
+    IntegerOverflow,
+    DivisionByZero,
+    OperandMustBeNumber,
+    OperandsMustBeNumbers,
+    OperandMustBeInteger,
+    OperandsMustBeIntegers,
+    NegativeShiftCount,
+    NegativeExponent,
+    OperandsNotComparable,
+    InvalidCodePoint,
+    IndexMustBeInteger,
+    IndexOutOfRange,
+    EmptyList,
+    KeyNotFound,
+    UnhashableKey,
+    TupleArityMismatch { expected: usize, found: usize },
+    OnlyInstancesHaveProperties,
+    UndefinedProperty,
+    SuperclassMustBeAClass,
+    ClassInheritsFromItself,
+    IsOperandMustBeAClass,
+    CanOnlyImplementTraits,
+    MissingTraitMethod,
+    ImplementsOperandMustBeATrait,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutOfFuel => write!(f, "Instruction budget exhausted."),
+            Self::Interrupted => write!(f, "Execution interrupted."),
+            Self::OutOfMemory => write!(f, "Out of memory."),
+            Self::IntegerOverflow => write!(f, "Integer overflow."),
+            Self::DivisionByZero => write!(f, "Division by zero."),
+            Self::OperandMustBeNumber => {
+                write!(f, "Operand must be a number.")
+            }
+            Self::OperandsMustBeNumbers => {
+                write!(f, "Operands must be numbers.")
+            }
+            Self::OperandMustBeInteger => {
+                write!(f, "Operand must be an integer.")
+            }
+            Self::OperandsMustBeIntegers => {
+                write!(f, "Operands must be integers.")
+            }
+            Self::NegativeShiftCount => write!(f, "Negative shift count."),
+            Self::NegativeExponent => {
+                write!(f, "Integer exponent must not be negative.")
+            }
+            Self::OperandsNotComparable => {
+                write!(f, "Operands must be two numbers or two chars.")
+            }
+            Self::InvalidCodePoint => write!(f, "Invalid Unicode code point."),
+            Self::IndexMustBeInteger => {
+                write!(f, "Index must be an integer.")
+            }
+            Self::IndexOutOfRange => write!(f, "Index out of range."),
+            Self::EmptyList => write!(f, "Pop from empty list."),
+            Self::KeyNotFound => write!(f, "Key not found."),
+            Self::UnhashableKey => write!(f, "Unhashable key."),
+            Self::TupleArityMismatch { expected, found } => write!(
+                f,
+                "Expected {expected} values to unpack but got {found}."
+            ),
+            Self::OnlyInstancesHaveProperties => {
+                write!(f, "Only instances have properties.")
+            }
+            Self::UndefinedProperty => write!(f, "Undefined property."),
+            Self::SuperclassMustBeAClass => {
+                write!(f, "Superclass must be a class.")
+            }
+            Self::ClassInheritsFromItself => {
+                write!(f, "A class can't inherit from itself.")
+            }
+            Self::IsOperandMustBeAClass => {
+                write!(f, "Right operand of 'is' must be a class.")
+            }
+            Self::CanOnlyImplementTraits => {
+                write!(f, "Can only implement traits.")
+            }
+            Self::MissingTraitMethod => {
+                write!(f, "Class does not define a required trait method.")
+            }
+            Self::ImplementsOperandMustBeATrait => {
+                write!(f, "Right operand of 'implements' must be a trait.")
+            }
+        }
+    }
+}
+
+// The memory limit set on the VM allocator is a recoverable runtime error
+impl From<TryReserveError> for RuntimeError {
+    fn from(_: TryReserveError) -> Self {
+        Self::OutOfMemory
+    }
+}
+
+/// Handle to interrupt a running VM from another thread.
+#[derive(Clone, Debug)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
 pub struct VM {
     pub allocator: VmAlloc,
+    #[cfg(feature = "debug-features")]
+    pub opcode_stats: Option<OpcodeStats>,
+    fuel: Option<u64>,
+    interrupt_requested: Arc<AtomicBool>,
 }
 
+impl VM {
+    pub fn new() -> Self {
+        Self {
+            allocator: Alloc::new(Global),
+            #[cfg(feature = "debug-features")]
+            opcode_stats: None,
+            fuel: None,
+            interrupt_requested: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn alloc_stats(&self) -> AllocStats {
+        self.allocator.stats()
+    }
+
+    /// Remaining instruction budget, `None` if unlimited.
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
 
+    /// Set the instruction budget. Fuel is consumed at backward jumps and
+    /// calls, so that any non-terminating script eventually runs out.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(Arc::clone(&self.interrupt_requested))
+    }
+
+    /// To be called by the dispatch loop on LOOP and CALL. A pending
+    /// interrupt is cleared when reported, so the VM can be reused.
+    pub(crate) fn consume_fuel(&mut self) -> Result<(), RuntimeError> {
+        if self.interrupt_requested.load(Ordering::Relaxed)
+            && self.interrupt_requested.swap(false, Ordering::Relaxed)
+        {
+            return Err(RuntimeError::Interrupted);
+        }
+        match self.fuel {
+            Some(0) => Err(RuntimeError::OutOfFuel),
+            Some(ref mut fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{list::TxList, string::TxString, value::Value};
+
+    #[test]
+    fn test_fuel() {
+        let mut tvm = VM::new();
+        assert_eq!(tvm.consume_fuel(), Ok(()));
+        tvm.set_fuel(Some(2));
+        assert_eq!(tvm.consume_fuel(), Ok(()));
+        assert_eq!(tvm.consume_fuel(), Ok(()));
+        assert_eq!(tvm.get_fuel(), Some(0));
+        assert_eq!(tvm.consume_fuel(), Err(RuntimeError::OutOfFuel));
+        assert_eq!(tvm.consume_fuel(), Err(RuntimeError::OutOfFuel));
+        tvm.set_fuel(Some(1));
+        assert_eq!(tvm.consume_fuel(), Ok(()));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut tvm = VM::new();
+        let handle = tvm.interrupt_handle();
+        std::thread::spawn(move || handle.interrupt())
+            .join()
+            .unwrap();
+        assert_eq!(tvm.consume_fuel(), Err(RuntimeError::Interrupted));
+        assert_eq!(tvm.consume_fuel(), Ok(()));
+    }
+
+    #[test]
+    fn test_out_of_memory() {
+        let tvm = VM::new();
+        tvm.allocator.set_limit_bytes(Some(4));
+        let values = [1, 2, 3].map(Value::Int);
+        assert!(matches!(
+            TxList::from_values(&tvm, &values),
+            Err(RuntimeError::OutOfMemory)
+        ));
+        assert!(matches!(
+            TxString::new(&tvm, "Hello"),
+            Err(RuntimeError::OutOfMemory)
+        ));
+        let mut list = TxList::new(&tvm);
+        assert_eq!(
+            list.push(&tvm, Value::Nil),
+            Err(RuntimeError::OutOfMemory)
+        );
+        assert!(list.is_empty());
+        assert_eq!(tvm.allocator.allocated_bytes(), 0);
+        tvm.allocator.set_limit_bytes(None);
+        assert_eq!(list.push(&tvm, Value::Nil), Ok(()));
+        unsafe {
+            list.destroy(&tvm);
+        }
+    }
+}
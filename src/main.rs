@@ -1,4 +1,5 @@
 use clap::Parser;
+use tx_runtime::vm::VM;
 
 // TODO: move to runtime
 #[cfg(feature = "debug-features")]
@@ -27,6 +28,10 @@ struct Args {
     #[arg(short, long, value_name = "TXT", conflicts_with = "file")]
     command: Option<String>,
 
+    /// Optimize the bytecode with a peephole pass before execution
+    #[arg(short = 'O')]
+    optimize: bool,
+
     /// Set debug option(s) (requires build with debug features)
     #[arg(short = 'D', value_name = "OPT", value_enum)]
     debug_opts: Vec<DebugOpt>,
@@ -48,6 +53,8 @@ enum DebugOpt {
     TraceExecution,
     /// Trace garbage collection
     TraceGC,
+    /// Count executed opcodes and opcode pairs, print them at exit
+    OpcodeStats,
 }
 
 fn main() {
@@ -60,8 +67,18 @@ fn main() {
     let args = Args::parse();
     println!("file: {:?}", args.file);
     println!("command: {:?}", args.command);
-    for opt in args.debug_opts {
+    println!("optimize: {:?}", args.optimize);
+    for opt in &args.debug_opts {
         println!("-D {:?}", opt);
     }
     println!("arguments {:?}", args.arguments);
+
+    let tvm = VM::new();
+    let trace_gc = args
+        .debug_opts
+        .iter()
+        .any(|opt| matches!(opt, DebugOpt::All | DebugOpt::TraceGC));
+    if trace_gc {
+        eprint!("{}", tvm.alloc_stats());
+    }
 }